@@ -14,11 +14,11 @@ async fn main() {
     let mut unwinder = UnwindBuilder::new().build();
 
     // Unwinding.
-    let mut iter = unwinder.unwind();
+    let iter = unwinder.unwind();
 
     // To simbolize propery, we get aslr offset.
     let aslr_offset = read_aslr_offset().unwrap();
-    while let Some(frame) = iter.next() {
+    for frame in iter {
         // Get symbol for each frame.
         let symbol = symbol_map
             .lookup(LookupAddress::Relative(
@@ -17,13 +17,23 @@ pub fn read_aslr_offset() -> Result<u64, Error> {
     imp::_read_aslr_offset()
 }
 
+/// Returns the address the vDSO is mapped at, to look up its symbols in the symbol map built
+/// with `SymbolMapBuilder::with_vdso`.
+#[cfg(target_os = "linux")]
+pub fn read_vdso_offset() -> Result<u64, Error> {
+    match crate::vdso::base() {
+        0 => Err(Error::NoMemoryMapping),
+        base => Ok(base),
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod imp {
     use super::Error;
     use std::{
         fs::File,
         io::{BufRead, BufReader},
-        path::PathBuf,
+        path::{Path, PathBuf},
     };
 
     pub(super) fn _read_aslr_offset() -> Result<u64, Error> {
@@ -48,7 +58,7 @@ mod imp {
             let pathname = parts.nth(4); // skip perms, offset, dev, inode
 
             // Only interested in the executable’s own mapping.
-            if pathname.map(|p| PathBuf::from(p) == exe).unwrap_or(false) {
+            if pathname.map(|p| exe == Path::new(p)).unwrap_or(false) {
                 if let Some(start_hex) = range.split('-').next() {
                     let addr = u64::from_str_radix(start_hex, 16)
                         .map_err(|_| Error::MemoryMapError("invalid addr".into()))?;
@@ -0,0 +1,77 @@
+//! Capturing the stack of the calling thread without setting up an unwinder.
+
+use crate::unwinder::{
+    regs::{current_regs, Regs},
+    Frame, StackUnwinder, UnwindBuilder,
+};
+use std::cell::RefCell;
+
+thread_local! {
+    /// The unwinder of this thread, built on first use. The unwind information of the
+    /// images is shared with the unwinders of the other threads.
+    static UNWINDER: RefCell<Option<StackUnwinder>> = const { RefCell::new(None) };
+}
+
+/// Captures the stack of the calling thread, starting with the caller of `capture`.
+///
+/// ```no_run
+/// let frames = hopframe::capture();
+/// println!("{} frames", frames.len());
+/// ```
+#[inline(never)]
+pub fn capture() -> Vec<Frame> {
+    let regs = current_regs!();
+    let mut frames = Vec::new();
+    walk(regs, &mut |frame| {
+        frames.push(frame);
+        true
+    });
+    frames
+}
+
+/// Calls `f` with each frame of the stack of the calling thread, starting with the caller of
+/// `trace`, until `f` returns `false`.
+///
+/// ```no_run
+/// let mut depth = 0;
+/// hopframe::trace(|_frame| {
+///     depth += 1;
+///     true
+/// });
+/// ```
+#[inline(never)]
+pub fn trace<F: FnMut(Frame) -> bool>(mut f: F) {
+    let regs = current_regs!();
+    walk(regs, &mut f);
+}
+
+/// Walks the stack from `regs`, captured in the frame of `capture` or `trace`, which is
+/// dropped.
+fn walk(regs: Regs, f: &mut dyn FnMut(Frame) -> bool) {
+    let mut walk_with = |unwinder: &mut StackUnwinder| {
+        #[cfg(target_arch = "x86_64")]
+        let frames = unwinder.unwind_from_regs(regs.pc, regs.sp, regs.fp);
+        #[cfg(target_arch = "aarch64")]
+        let frames = unwinder.unwind_from_regs(regs.pc, regs.sp, regs.fp, regs.lr);
+        for frame in frames.skip(1) {
+            if !f(frame) {
+                break;
+            }
+        }
+    };
+
+    // The thread-local unwinder is unavailable if `f` captures a stack itself, or while the
+    // thread-locals of the thread are being destroyed. Use a temporary one then.
+    let walked = UNWINDER
+        .try_with(|unwinder| match unwinder.try_borrow_mut() {
+            Ok(mut unwinder) => {
+                walk_with(unwinder.get_or_insert_with(|| UnwindBuilder::new().build()));
+                true
+            }
+            Err(_) => false,
+        })
+        .unwrap_or(false);
+    if !walked {
+        walk_with(&mut UnwindBuilder::new().build());
+    }
+}
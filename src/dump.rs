@@ -0,0 +1,98 @@
+//! Dumping the stacks of every thread of this process, like a JVM thread dump.
+
+use crate::unwinder::{
+    gettid, regs::current_regs, Frame, TerminationReason, ThreadCapture, ThreadCaptureError,
+    UnwindBuilder,
+};
+use std::{fmt, io, time::Duration};
+
+/// How long to wait for each thread to respond before giving up on it.
+const THREAD_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The stack of one thread of a [`dump_all_threads`] dump.
+#[derive(Debug)]
+pub struct ThreadStack {
+    pub tid: libc::pid_t,
+    /// The name of the thread, from `/proc/self/task/<tid>/comm`.
+    pub name: String,
+    pub frames: Result<Vec<Frame>, ThreadCaptureError>,
+    /// Why the walk ended, if the stack was captured.
+    pub termination_reason: Option<TerminationReason>,
+}
+
+impl fmt::Display for ThreadStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\"{}\" tid={}", self.name, self.tid)?;
+        match &self.frames {
+            Ok(frames) => {
+                for (index, frame) in frames.iter().enumerate() {
+                    writeln!(f, "  #{index:<3} 0x{:016x}", frame.address())?;
+                }
+                match &self.termination_reason {
+                    Some(reason) if !reason.is_complete() => writeln!(f, "  <{reason}>"),
+                    _ => Ok(()),
+                }
+            }
+            Err(error) => writeln!(f, "  <stack not captured: {error}>"),
+        }
+    }
+}
+
+/// Captures the stack of every thread of this process, ordered by thread ID.
+///
+/// Threads are captured one at a time with
+/// [`unwind_thread`](crate::unwinder::StackUnwinder::unwind_thread), and the calling thread
+/// directly. The other threads are interrupted with `SIGRTMIN + 4`, whose handler is installed
+/// on first use. Threads that exit during the dump are left out, and threads that don't
+/// respond in time, or can't be interrupted, are reported with an error.
+///
+/// The stack of the calling thread starts with the caller of `dump_all_threads`.
+#[inline(never)]
+pub fn dump_all_threads() -> io::Result<Vec<ThreadStack>> {
+    let regs = current_regs!();
+    let mut tids = std::fs::read_dir("/proc/self/task")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect::<Vec<libc::pid_t>>();
+    tids.sort_unstable();
+
+    // Building the unwinder registers the loaded images, and every thread is then walked
+    // with them, without re-reading them.
+    let mut unwinder = UnwindBuilder::new().with_skip_internal_frame(true).build();
+    let current = gettid();
+    let stacks = tids
+        .into_iter()
+        .filter_map(|tid| {
+            let capture = if tid == current {
+                let mut iter = unwinder.unwind_from_without_refresh(regs);
+                let frames = iter.by_ref().collect();
+                Ok(ThreadCapture {
+                    frames,
+                    termination_reason: iter
+                        .termination_reason()
+                        .cloned()
+                        .expect("the walk has ended"),
+                })
+            } else {
+                unwinder.unwind_thread_without_refresh(tid, THREAD_TIMEOUT)
+            };
+            let (frames, termination_reason) = match capture {
+                Ok(capture) => (Ok(capture.frames), Some(capture.termination_reason)),
+                Err(ThreadCaptureError::NoSuchThread) => return None,
+                Err(error) => (Err(error), None),
+            };
+            Some(ThreadStack {
+                tid,
+                name: thread_name(tid),
+                frames,
+                termination_reason,
+            })
+        })
+        .collect();
+    Ok(stacks)
+}
+
+fn thread_name(tid: libc::pid_t) -> String {
+    std::fs::read_to_string(format!("/proc/self/task/{tid}/comm"))
+        .map(|name| name.trim_end().to_owned())
+        .unwrap_or_default()
+}
@@ -19,3 +19,22 @@ pub mod symbolize;
 
 #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 pub mod unwinder;
+
+#[cfg(target_os = "linux")]
+mod vdso;
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod dump;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use dump::{dump_all_threads, ThreadStack};
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+mod capture;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use capture::{capture, trace};
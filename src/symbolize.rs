@@ -1,14 +1,21 @@
 use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use wholesym::{samply_symbols::SymbolMapTrait, LibraryInfo};
 
 pub use wholesym::{LookupAddress, SymbolManager, SymbolManagerConfig, SymbolMap};
 
 /// Builder for [`SymbolMap`].
+#[derive(Default)]
 pub struct SymbolMapBuilder<'a> {
     binary_path: Option<&'a Path>,
+    #[cfg(target_os = "linux")]
+    vdso: bool,
 }
 impl<'a> SymbolMapBuilder<'a> {
     pub fn new() -> Self {
-        Self { binary_path: None }
+        Self::default()
     }
 
     pub fn with_binary_path(mut self, binary_path: &'a Path) -> Self {
@@ -16,12 +23,39 @@ impl<'a> SymbolMapBuilder<'a> {
         self
     }
 
+    /// Builds the symbol map of the vDSO of this process instead of a binary, from its
+    /// dynamic symbols. Look up addresses relative to
+    /// [`read_vdso_offset`](crate::aslr::read_vdso_offset).
+    ///
+    /// The map is empty if the process has no vDSO, e.g. under qemu-user before 8.1.
+    #[cfg(target_os = "linux")]
+    pub fn with_vdso(mut self) -> Self {
+        self.vdso = true;
+        self
+    }
+
     pub async fn build(self) -> SymbolMap {
         let config = SymbolManagerConfig::default();
-        let symbol_manager = SymbolManager::with_config(config);
-        if self.binary_path.is_some() {
+        #[allow(unused_mut)]
+        let mut symbol_manager = SymbolManager::with_config(config);
+        #[cfg(target_os = "linux")]
+        if self.vdso {
+            let symbol_map = vdso::VdsoSymbolMap::new().unwrap_or_else(vdso::VdsoSymbolMap::empty);
+            let debug_id = symbol_map.debug_id();
+            let info = LibraryInfo {
+                debug_name: Some(vdso::NAME.to_owned()),
+                debug_id: Some(debug_id),
+                ..Default::default()
+            };
+            symbol_manager.add_known_library_symbols(info, Arc::new(symbol_map));
+            return symbol_manager
+                .load_symbol_map(vdso::NAME, debug_id)
+                .await
+                .unwrap();
+        }
+        if let Some(binary_path) = self.binary_path {
             symbol_manager
-                .load_symbol_map_for_binary_at_path(&self.binary_path.unwrap(), None)
+                .load_symbol_map_for_binary_at_path(binary_path, None)
                 .await
                 .unwrap()
         } else {
@@ -34,3 +68,93 @@ impl<'a> SymbolMapBuilder<'a> {
         }
     }
 }
+
+#[cfg(target_os = "linux")]
+mod vdso {
+    use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+    use std::borrow::Cow;
+    use wholesym::{
+        debugid::DebugId,
+        samply_symbols::{debug_id_for_object, SymbolMapTrait},
+        LookupAddress, SymbolInfo, SyncAddressInfo,
+    };
+
+    /// The name of the vDSO, as reported by the dynamic linker.
+    pub(super) const NAME: &str = "linux-vdso.so.1";
+
+    /// The functions of the vDSO, read from its image in memory.
+    pub(super) struct VdsoSymbolMap {
+        debug_id: DebugId,
+        /// Relative address, size and name of each function, sorted by address.
+        symbols: Vec<(u32, u32, String)>,
+    }
+
+    impl VdsoSymbolMap {
+        pub(super) fn new() -> Option<Self> {
+            let file = object::File::parse(crate::vdso::image()?).ok()?;
+            let debug_id = debug_id_for_object(&file)?;
+            // Relative addresses start at the first segment.
+            let base = file.segments().next()?.address();
+            let mut symbols: Vec<_> = file
+                .dynamic_symbols()
+                .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.address() != 0)
+                .filter_map(|symbol| {
+                    let name = symbol.name().ok()?.to_owned();
+                    Some(((symbol.address() - base) as u32, symbol.size() as u32, name))
+                })
+                .collect();
+            symbols.sort_unstable();
+            // Several names often alias one function.
+            symbols.dedup_by_key(|(address, ..)| *address);
+            Some(Self { debug_id, symbols })
+        }
+
+        /// Returns a map without symbols, for a process without a vDSO.
+        pub(super) fn empty() -> Self {
+            Self {
+                debug_id: DebugId::nil(),
+                symbols: Vec::new(),
+            }
+        }
+    }
+
+    impl SymbolMapTrait for VdsoSymbolMap {
+        fn debug_id(&self) -> DebugId {
+            self.debug_id
+        }
+
+        fn symbol_count(&self) -> usize {
+            self.symbols.len()
+        }
+
+        fn iter_symbols(&self) -> Box<dyn Iterator<Item = (u32, Cow<'_, str>)> + '_> {
+            Box::new(
+                self.symbols
+                    .iter()
+                    .map(|(address, _, name)| (*address, Cow::Borrowed(name.as_str()))),
+            )
+        }
+
+        fn lookup_sync(&self, address: LookupAddress) -> Option<SyncAddressInfo> {
+            let LookupAddress::Relative(address) = address else {
+                return None;
+            };
+            let index = self
+                .symbols
+                .partition_point(|(start, ..)| *start <= address)
+                .checked_sub(1)?;
+            let (start, size, name) = &self.symbols[index];
+            if address - start >= *size {
+                return None;
+            }
+            Some(SyncAddressInfo {
+                symbol: SymbolInfo {
+                    address: *start,
+                    size: Some(*size),
+                    name: name.clone(),
+                },
+                frames: None,
+            })
+        }
+    }
+}
@@ -1,3 +1,45 @@
+mod frame;
+mod live;
+#[cfg(target_os = "linux")]
+mod module;
+mod offline;
+mod options;
+#[cfg(target_os = "linux")]
+mod prologue;
+mod reader;
+pub(crate) mod regs;
+#[cfg(target_os = "linux")]
+mod remote;
+#[cfg(target_os = "linux")]
+mod scan;
+#[cfg(target_os = "linux")]
+mod signal;
+mod snapshot;
+mod termination;
+#[cfg(target_os = "linux")]
+mod thread;
+mod usage;
+mod watermark;
+
+pub use frame::{Frame, FrameKind};
+pub use framehop::aarch64::PtrAuthMask;
+pub use live::{LiveArch, LiveStackUnwinder, LiveUnwindBuilder, LiveUnwindIterator};
+#[cfg(target_os = "linux")]
+pub use module::CodeUnwindInfo;
+pub use offline::{Arch, SnapshotIterator, SnapshotUnwinder};
+pub use reader::StackBounds;
+#[cfg(target_os = "linux")]
+pub use remote::{RemoteThreadStack, RemoteUnwinder};
+pub use snapshot::{SnapshotModule, StackSnapshot};
+pub use termination::TerminationReason;
+#[cfg(target_os = "linux")]
+pub use thread::{gettid, ThreadCapture, ThreadCaptureError};
+pub use usage::{frame_sizes, stack_depth, FunctionStackUsage, StackUsage};
+pub use watermark::StackWatermark;
+
+/// The section data of the registered modules, shared between the unwinders of the process.
+pub(crate) type ModuleData = std::sync::Arc<[u8]>;
+
 // Architecture-specific modules
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
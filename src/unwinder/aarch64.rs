@@ -1,86 +1,130 @@
 use framehop::{
-    aarch64::{CacheAarch64, UnwindRegsAarch64, UnwinderAarch64},
-    FrameAddress, Unwinder,
+    aarch64::{CacheAarch64, PtrAuthMask, UnwindRegsAarch64, UnwinderAarch64},
+    MustNotAllocateDuringUnwind,
 };
 use std::arch::asm;
 
-/// load libraries, configure cache or unwinder, etc.
-#[derive(Default)]
-pub struct UnwindBuilderAarch64 {}
+use super::{
+    live::{imp::LiveArchImpl, LiveArch, LiveStackUnwinder, LiveUnwindBuilder, LiveUnwindIterator},
+    reader::StackBounds,
+    regs::Regs,
+    ModuleData,
+};
 
-impl UnwindBuilderAarch64 {
-    pub fn new() -> Self {
-        Self::default()
+/// The aarch64 architecture, for [`LiveStackUnwinder`].
+pub enum LiveAarch64 {}
+
+impl LiveArch for LiveAarch64 {}
+
+impl LiveArchImpl for LiveAarch64 {
+    type Regs = UnwindRegsAarch64;
+    type Cache = CacheAarch64<MustNotAllocateDuringUnwind>;
+    type Unwinder = UnwinderAarch64<ModuleData, MustNotAllocateDuringUnwind>;
+    type Config = PtrAuthMask;
+
+    const SCANNED_RETURN_ADDRESS_IN_FRAME_RECORD: bool = true;
+
+    fn default_config() -> PtrAuthMask {
+        default_ptr_auth_mask()
+    }
+
+    fn new_unwinder() -> Self::Unwinder {
+        UnwinderAarch64::new()
     }
-    pub fn build(self) -> StackUnwinderAarch64 {
-        StackUnwinderAarch64 {
-            cache: CacheAarch64::<_>::new(),
-            unwinder: UnwinderAarch64::new(),
-            closure: Box::new(|addr: u64| {
-                // Unaligned address
-                assert!(addr % 8 == 0);
-                unsafe { Ok(*(addr as *const u64)) }
-            }),
-        }
+
+    fn new_cache() -> Self::Cache {
+        CacheAarch64::new_in()
     }
-}
 
-pub struct StackUnwinderAarch64 {
-    cache: CacheAarch64,
-    unwinder: UnwinderAarch64<Vec<u8>>,
-    closure: Box<dyn FnMut(u64) -> Result<u64, ()>>,
-}
+    fn regs(ptr_auth_mask: PtrAuthMask, _pc: u64, sp: u64, fp: u64, lr: u64) -> UnwindRegsAarch64 {
+        UnwindRegsAarch64::new_with_ptr_auth_mask(ptr_auth_mask, lr, sp, fp)
+    }
 
-impl StackUnwinderAarch64 {
-    pub fn unwind(&mut self) -> UnwindIterator<'_> {
-        #[allow(unused)]
-        let (pc, regs) = {
-            let mut pc = 0;
-            let mut lr = 0;
-            let mut sp = 0;
-            let mut fp = 0;
-            unsafe {
-                // Get current PC (program counter)
-                asm!("adr {}, .", out(reg) pc);
-                // Get LR (link register - x30)
-                asm!("mov {}, x30", out(reg) lr);
-                // Get SP (stack pointer)
-                asm!("mov {}, sp", out(reg) sp);
-                // Get FP (frame pointer - x29)
-                asm!("mov {}, x29", out(reg) fp);
-            }
-            (pc, UnwindRegsAarch64::new(lr, sp, fp))
-        };
-
-        let iter = self
-            .unwinder
-            .iter_frames(pc, regs, &mut self.cache, &mut self.closure);
-
-        UnwindIterator::new(iter)
+    fn sp(regs: &UnwindRegsAarch64) -> u64 {
+        regs.sp()
+    }
+
+    fn fp(regs: &UnwindRegsAarch64) -> u64 {
+        regs.fp()
+    }
+
+    fn set_sp(regs: &mut UnwindRegsAarch64, sp: u64) {
+        regs.set_sp(sp);
+    }
+
+    fn set_fp(regs: &mut UnwindRegsAarch64, fp: u64) {
+        regs.set_fp(fp);
+    }
+
+    fn strip_return_address(ptr_auth_mask: PtrAuthMask, address: u64) -> u64 {
+        ptr_auth_mask.strip_ptr_auth(address)
+    }
+
+    fn caller_before_frame_setup(
+        regs: &UnwindRegsAarch64,
+        _read_stack: &mut dyn FnMut(u64) -> Result<u64, ()>,
+    ) -> Result<(u64, u64), u64> {
+        // The return address is still in the link register.
+        Ok((regs.lr(), regs.sp()))
     }
 }
 
-pub type UnwindIteratorAarch64<'a> = framehop::UnwindIterator<
-    'a,
-    'a,
-    'a,
-    UnwinderAarch64<Vec<u8>>,
-    Box<dyn FnMut(u64) -> Result<u64, ()>>,
->;
+pub type UnwindBuilderAarch64 = LiveUnwindBuilder<LiveAarch64>;
+pub type StackUnwinderAarch64 = LiveStackUnwinder<LiveAarch64>;
+pub type UnwindIterator<'a> = LiveUnwindIterator<'a, LiveAarch64>;
 
-pub struct UnwindIterator<'a> {
-    inner: UnwindIteratorAarch64<'a>,
+impl UnwindBuilderAarch64 {
+    /// Strips pointer-authentication bits from return addresses with `ptr_auth_mask`, for
+    /// code built with `-mbranch-protection=pac-ret`. By default, the mask is the one applied
+    /// by the `xpaclri` instruction on this CPU.
+    pub fn with_ptr_auth_mask(mut self, ptr_auth_mask: PtrAuthMask) -> Self {
+        self.config = Some(ptr_auth_mask);
+        self
+    }
 }
 
-impl<'a> UnwindIterator<'a> {
-    fn new(inner: UnwindIteratorAarch64<'a>) -> Self {
-        Self { inner }
+/// Returns the bits of a code address that are kept by the `xpaclri` instruction, i.e. that
+/// are not pointer-authentication bits. Nothing is stripped on CPUs without pointer
+/// authentication, where `xpaclri` does nothing.
+pub(crate) fn default_ptr_auth_mask() -> PtrAuthMask {
+    // All bits set except bit 55, which selects the user address range.
+    const ADDRESS: u64 = 0xFF7F_FFFF_FFFF_FFFF;
+    let mut stripped = ADDRESS;
+    // `xpaclri` is encoded as `hint #7` so that it assembles without pointer authentication.
+    unsafe { asm!("hint #7", inout("x30") stripped, options(nomem, nostack)) };
+    if stripped == ADDRESS {
+        PtrAuthMask::new_no_strip()
+    } else {
+        PtrAuthMask(stripped)
     }
 }
 
-impl<'a> Iterator for UnwindIterator<'a> {
-    type Item = FrameAddress;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().ok().flatten()
+impl StackUnwinderAarch64 {
+    /// Unwinds the stack of the current thread starting from the given register values,
+    /// e.g. ones saved by a signal handler. `pc` is the first frame, and `lr` is needed for
+    /// it if it is a leaf function that hasn't saved its return address on the stack.
+    pub fn unwind_from_regs(&mut self, pc: u64, sp: u64, fp: u64, lr: u64) -> UnwindIterator<'_> {
+        self.iter_frames(Regs { pc, sp, fp, lr }, 0)
+    }
+
+    /// Unwinds the stack of a suspended fiber or coroutine from the registers saved when it
+    /// was switched out, without switching to it. `pc` is the first frame.
+    ///
+    /// Only `stack`, the memory allocated for the stack of the fiber, is read, so a stale or
+    /// corrupted context ends the walk instead of crashing the process.
+    ///
+    /// # Safety
+    ///
+    /// The walk reads `stack` directly, so it must be mapped, readable memory until the
+    /// returned iterator is dropped, e.g. the fiber must not be freed meanwhile.
+    pub unsafe fn unwind_fiber(
+        &mut self,
+        pc: u64,
+        sp: u64,
+        fp: u64,
+        lr: u64,
+        stack: StackBounds,
+    ) -> UnwindIterator<'_> {
+        unsafe { self.fiber(Regs { pc, sp, fp, lr }, stack) }
     }
 }
@@ -0,0 +1,108 @@
+//! The frames yielded by stack walks.
+
+use framehop::FrameAddress;
+
+/// How the address of a [`Frame`] was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FrameKind {
+    /// The address is the program counter of the first frame of the walk.
+    InstructionPointer,
+    /// The address is a return address read while unwinding, so it points after the call
+    /// instruction.
+    ReturnAddress,
+    /// The address is the program counter of code interrupted by a signal, as saved by the
+    /// kernel on the stack of the signal handler.
+    InterruptedBySignal,
+    /// The address is a return address found by scanning the stack, because the frame it
+    /// returns to could not be unwound otherwise. It may be a stale value left by an earlier
+    /// call, so this frame and the ones after it are guesses.
+    StackScanned,
+}
+
+/// A frame of a walked stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    address: u64,
+    kind: FrameKind,
+    stack_pointer: u64,
+    frame_pointer: u64,
+    module_index: Option<usize>,
+}
+
+impl Frame {
+    pub(crate) fn new(
+        address: FrameAddress,
+        stack_pointer: u64,
+        frame_pointer: u64,
+        module_index: Option<usize>,
+    ) -> Self {
+        let kind = match address {
+            FrameAddress::InstructionPointer(_) => FrameKind::InstructionPointer,
+            FrameAddress::ReturnAddress(_) => FrameKind::ReturnAddress,
+        };
+        Self {
+            address: address.address(),
+            kind,
+            stack_pointer,
+            frame_pointer,
+            module_index,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_kind(mut self, kind: FrameKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// The program counter or the return address, as found on the stack.
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// An address inside the instruction being executed in this frame, to look up symbols or
+    /// unwind information. For return addresses, this is the address of the call instruction.
+    pub fn address_for_lookup(&self) -> u64 {
+        match self.kind {
+            FrameKind::InstructionPointer | FrameKind::InterruptedBySignal => self.address,
+            FrameKind::ReturnAddress | FrameKind::StackScanned => self.address - 1,
+        }
+    }
+
+    pub fn kind(&self) -> FrameKind {
+        self.kind
+    }
+
+    /// The value of the stack pointer while this frame executes. For every frame but the
+    /// first, this is the canonical frame address (CFA) of the frame it called.
+    pub fn stack_pointer(&self) -> u64 {
+        self.stack_pointer
+    }
+
+    /// The value of the frame pointer register in this frame. Without frame pointers, it
+    /// may hold an unrelated value.
+    pub fn frame_pointer(&self) -> u64 {
+        self.frame_pointer
+    }
+
+    /// The index of the module containing the address, if any. Modules keep their index
+    /// while they are loaded, see [`StackUnwinder::module`](super::StackUnwinder::module).
+    pub fn module_index(&self) -> Option<usize> {
+        self.module_index
+    }
+}
+
+impl From<Frame> for FrameAddress {
+    fn from(frame: Frame) -> Self {
+        match frame.kind {
+            FrameKind::InstructionPointer | FrameKind::InterruptedBySignal => {
+                FrameAddress::InstructionPointer(frame.address)
+            }
+            FrameKind::ReturnAddress | FrameKind::StackScanned => {
+                FrameAddress::from_return_address(frame.address)
+                    .expect("return addresses are not null")
+            }
+        }
+    }
+}
@@ -0,0 +1,795 @@
+//! Walking the stacks of this process. The walk is the same on every architecture, which only
+//! provides its registers and framehop unwinder, see [`LiveArch`].
+
+use framehop::{FrameAddress, Module, Unwinder};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use super::{
+    frame::Frame,
+    options::UnwindOptions,
+    reader::{CurrentThreadStack, StackBounds, StackReader},
+    regs::{current_regs, Regs},
+    snapshot::StackSnapshot,
+    ModuleData, TerminationReason,
+};
+#[cfg(target_os = "linux")]
+use super::{
+    module::Modules, prologue::FrameSetup, scan, signal, thread, FrameKind, ThreadCapture,
+    ThreadCaptureError,
+};
+
+/// Default limit of the frames returned by `unwind_thread`.
+#[cfg(target_os = "linux")]
+const MAX_THREAD_FRAMES: usize = 512;
+
+/// An architecture whose stacks [`LiveStackUnwinder`] can walk, i.e. the one of this process.
+pub trait LiveArch: imp::LiveArchImpl {}
+
+pub(super) mod imp {
+    use super::{Module, ModuleData, Unwinder};
+
+    /// The registers and the framehop unwinder of an architecture.
+    pub trait LiveArchImpl: Sized {
+        type Regs: Copy;
+        type Cache;
+        type Unwinder: Unwinder<
+            UnwindRegs = Self::Regs,
+            Cache = Self::Cache,
+            Module = Module<ModuleData>,
+        >;
+        /// Settings of the walks that only exist on this architecture.
+        type Config: Copy;
+
+        /// Whether a return address found by scanning the stack is always saved in a frame
+        /// record, after the frame pointer of the caller. On aarch64, the link register is
+        /// only saved on the stack that way.
+        const SCANNED_RETURN_ADDRESS_IN_FRAME_RECORD: bool;
+
+        fn default_config() -> Self::Config;
+        fn new_unwinder() -> Self::Unwinder;
+        fn new_cache() -> Self::Cache;
+        fn regs(config: Self::Config, pc: u64, sp: u64, fp: u64, lr: u64) -> Self::Regs;
+        fn sp(regs: &Self::Regs) -> u64;
+        fn fp(regs: &Self::Regs) -> u64;
+        fn set_sp(regs: &mut Self::Regs, sp: u64);
+        fn set_fp(regs: &mut Self::Regs, fp: u64);
+
+        /// Strips the bits of a return address that are not part of the address.
+        fn strip_return_address(config: Self::Config, address: u64) -> u64;
+
+        /// Returns the return address and the stack pointer of the caller of a function that
+        /// has not saved anything on the stack yet, or the address that could not be read.
+        fn caller_before_frame_setup(
+            regs: &Self::Regs,
+            read_stack: &mut dyn FnMut(u64) -> Result<u64, ()>,
+        ) -> Result<(u64, u64), u64>;
+    }
+}
+
+/// load libraries, configure cache or unwinder, etc.
+pub struct LiveUnwindBuilder<A: LiveArch> {
+    options: UnwindOptions,
+    pub(super) config: Option<A::Config>,
+}
+
+impl<A: LiveArch> Default for LiveUnwindBuilder<A> {
+    fn default() -> Self {
+        Self {
+            options: UnwindOptions::default(),
+            config: None,
+        }
+    }
+}
+
+impl<A: LiveArch> LiveUnwindBuilder<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops a walk after `max_frames` frames, so that a deep stack can't make it unbounded.
+    pub fn with_max_frames(mut self, max_frames: usize) -> Self {
+        self.options.max_frames = Some(max_frames);
+        self
+    }
+
+    /// Drops the first `skip_frames` frames of a walk.
+    pub fn with_skip_frames(mut self, skip_frames: usize) -> Self {
+        self.options.skip_frames = skip_frames;
+        self
+    }
+
+    /// Drops the frame of [`unwind`](LiveStackUnwinder::unwind) itself, which otherwise comes
+    /// first, so that the walk starts with the frame of its caller.
+    pub fn with_skip_internal_frame(mut self, skip_internal_frame: bool) -> Self {
+        self.options.skip_internal_frame = skip_internal_frame;
+        self
+    }
+
+    /// Falls back to scanning the stack when a frame can be unwound neither with unwind
+    /// information nor with frame pointers, e.g. in C libraries built with
+    /// `-fomit-frame-pointer` and without unwind tables.
+    ///
+    /// The first value above the stack pointer that points right after a call instruction in
+    /// a known module is taken as the return address. It may be a stale value left by an
+    /// earlier call, so such frames are marked as
+    /// [`FrameKind::StackScanned`](super::FrameKind::StackScanned).
+    #[cfg(target_os = "linux")]
+    pub fn with_stack_scanning(mut self, stack_scanning: bool) -> Self {
+        self.options.stack_scanning = stack_scanning;
+        self
+    }
+
+    /// Reads the unwind information of the loaded images from their `PT_GNU_EH_FRAME`
+    /// segments in memory instead of their files, so that walks work in sandboxed processes
+    /// that can't open files or read `/proc`. Images without such a segment are then unwound
+    /// with frame pointers.
+    #[cfg(target_os = "linux")]
+    pub fn with_unwind_info_from_memory(mut self, from_memory: bool) -> Self {
+        self.options.unwind_info_from_memory = from_memory;
+        self
+    }
+
+    pub fn build(self) -> LiveStackUnwinder<A> {
+        #[allow(unused_mut)]
+        let mut unwinder = LiveStackUnwinder {
+            cache: A::new_cache(),
+            unwinder: A::new_unwinder(),
+            #[cfg(target_os = "linux")]
+            modules: if self.options.unwind_info_from_memory {
+                Modules::from_memory()
+            } else {
+                Modules::new()
+            },
+            #[cfg(target_os = "linux")]
+            other_modules: ModuleSet {
+                cache: A::new_cache(),
+                unwinder: A::new_unwinder(),
+                modules: Modules::new(),
+            },
+            #[cfg(target_os = "linux")]
+            snapshot_modules_active: false,
+            stack: CurrentThreadStack::new(),
+            options: self.options,
+            config: self.config.unwrap_or_else(A::default_config),
+        };
+        // Register the DWARF CFI of every loaded image, so that frame pointers are not required.
+        #[cfg(target_os = "linux")]
+        unwinder.modules.refresh(&mut unwinder.unwinder);
+        unwinder
+    }
+}
+
+/// A set of modules, with the framehop unwinder they are registered with.
+#[cfg(target_os = "linux")]
+struct ModuleSet<A: LiveArch> {
+    cache: A::Cache,
+    unwinder: A::Unwinder,
+    modules: Modules,
+}
+
+pub struct LiveStackUnwinder<A: LiveArch> {
+    // Walks never allocate, so that `capture_into` can be used in signal handlers.
+    cache: A::Cache,
+    unwinder: A::Unwinder,
+    #[cfg(target_os = "linux")]
+    modules: Modules,
+    /// The modules of the last unwound snapshot while the ones of this process are in use,
+    /// and the other way around. See [`use_snapshot_modules`](Self::use_snapshot_modules).
+    #[cfg(target_os = "linux")]
+    other_modules: ModuleSet<A>,
+    #[cfg(target_os = "linux")]
+    snapshot_modules_active: bool,
+    stack: CurrentThreadStack,
+    options: UnwindOptions,
+    config: A::Config,
+}
+
+impl<A: LiveArch> LiveStackUnwinder<A> {
+    pub fn unwind(&mut self) -> LiveUnwindIterator<'_, A> {
+        let regs = current_regs!();
+        let internal_frames = usize::from(self.options.skip_internal_frame);
+        self.iter_frames(regs, internal_frames)
+    }
+
+    /// Walks the stack of the calling thread into `frames` and returns the number of frames
+    /// written: the program counter first, then the raw return addresses.
+    ///
+    /// Unlike [`unwind`](Self::unwind), this neither allocates nor takes locks, so it can be
+    /// called from a signal handler (e.g. for `SIGPROF` or a crash). In exchange, images
+    /// loaded since the last walk are not registered, and the stack can only be read on
+    /// threads the unwinder has already been used on; elsewhere the walk stops after the
+    /// first frame.
+    pub fn capture_into(&mut self, frames: &mut [u64]) -> usize {
+        let regs = current_regs!();
+        #[cfg(target_os = "linux")]
+        self.use_snapshot_modules(false);
+        let reader = self.stack.cached_reader();
+        let skip_frames = self.options.skip_frames + usize::from(self.options.skip_internal_frame);
+        let iter = self.walk_without_refresh(reader, regs, skip_frames);
+
+        let mut count = 0;
+        for (slot, frame) in frames.iter_mut().zip(iter) {
+            *slot = frame.address();
+            count += 1;
+        }
+        count
+    }
+
+    /// Unwinds the stack of the code interrupted by a signal, from the registers saved in
+    /// the `ucontext` passed to a `SA_SIGINFO` signal handler.
+    ///
+    /// Like [`capture_into`](Self::capture_into), the walk neither allocates nor takes locks,
+    /// so images loaded since the last walk are not registered, and the stack can only be read
+    /// on threads the unwinder has already been used on.
+    #[cfg(target_os = "linux")]
+    pub fn unwind_from_ucontext(
+        &mut self,
+        ucontext: &libc::ucontext_t,
+    ) -> LiveUnwindIterator<'_, A> {
+        self.use_snapshot_modules(false);
+        let reader = self.stack.cached_reader();
+        let skip_frames = self.options.skip_frames;
+        self.walk_without_refresh(reader, Regs::from_ucontext(ucontext), skip_frames)
+    }
+
+    /// Like [`unwind_fiber`](LiveStackUnwinder::unwind_fiber), from the registers saved in a
+    /// `ucontext` by `swapcontext` or `getcontext`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`unwind_fiber`](LiveStackUnwinder::unwind_fiber), `stack` must be mapped,
+    /// readable memory until the returned iterator is dropped.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn unwind_fiber_from_ucontext(
+        &mut self,
+        ucontext: &libc::ucontext_t,
+        stack: StackBounds,
+    ) -> LiveUnwindIterator<'_, A> {
+        unsafe { self.fiber(Regs::from_ucontext(ucontext), stack) }
+    }
+
+    /// Captures the stack of the thread `tid` of this process, e.g. to find out what a stuck
+    /// worker is doing. See [`gettid`](super::gettid).
+    ///
+    /// The thread is interrupted with the real-time signal `SIGRTMIN + 4` and kept waiting in
+    /// its handler while its stack is walked from the calling thread. The handler is installed
+    /// on first use, which fails with [`SignalInUse`](super::ThreadCaptureError::SignalInUse)
+    /// if the application handles that signal. `timeout` bounds the wait for the handler to
+    /// run, e.g. if the thread blocks signals. At most `max_frames` frames are returned, and
+    /// never more than 512, since the frames are stored in a buffer allocated before the
+    /// thread is interrupted.
+    #[cfg(target_os = "linux")]
+    pub fn unwind_thread(
+        &mut self,
+        tid: libc::pid_t,
+        timeout: Duration,
+    ) -> Result<ThreadCapture, ThreadCaptureError> {
+        self.refresh_modules();
+        self.unwind_thread_without_refresh(tid, timeout)
+    }
+
+    /// Like [`unwind_thread`](Self::unwind_thread), without registering the images loaded
+    /// since the last refresh first.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn unwind_thread_without_refresh(
+        &mut self,
+        tid: libc::pid_t,
+        timeout: Duration,
+    ) -> Result<ThreadCapture, ThreadCaptureError> {
+        // The suspended thread may hold the allocator's lock, so everything that allocates
+        // happens up front.
+        self.use_snapshot_modules(false);
+        let mappings = thread::mappings()?;
+        let capacity = self
+            .options
+            .max_frames
+            .map_or(MAX_THREAD_FRAMES, |max_frames| {
+                max_frames.min(MAX_THREAD_FRAMES)
+            });
+        let mut frames = Vec::with_capacity(capacity);
+        let skip_frames = self.options.skip_frames;
+
+        let suspended = thread::SuspendedThread::suspend(tid, timeout)?;
+        let regs = suspended.regs;
+        let reader = StackReader::new(thread::stack_bounds(&mappings, regs.sp));
+        let mut iter = self.walk_without_refresh(reader, regs, skip_frames);
+        let termination_reason = loop {
+            if frames.len() == frames.capacity() {
+                break TerminationReason::DepthLimit;
+            }
+            match iter.next() {
+                Some(frame) => frames.push(frame),
+                None => {
+                    break iter
+                        .termination_reason()
+                        .cloned()
+                        .expect("the walk has ended")
+                }
+            }
+        };
+        drop(suspended);
+        Ok(ThreadCapture {
+            frames,
+            termination_reason,
+        })
+    }
+
+    /// Walks the stack of the calling thread from `regs`, taken in a frame that is still
+    /// active, without re-reading the loaded images. The frame of `regs` is the internal frame
+    /// dropped by [`with_skip_internal_frame`](LiveUnwindBuilder::with_skip_internal_frame).
+    #[cfg(target_os = "linux")]
+    pub(crate) fn unwind_from_without_refresh(&mut self, regs: Regs) -> LiveUnwindIterator<'_, A> {
+        self.use_snapshot_modules(false);
+        let reader = self.stack.reader();
+        let skip_frames = self.options.skip_frames + usize::from(self.options.skip_internal_frame);
+        self.walk_without_refresh(reader, regs, skip_frames)
+    }
+
+    /// Registers the images loaded since the last refresh, and forgets the ones unloaded
+    /// since, e.g. by `dlopen` and `dlclose`. [`unwind`](Self::unwind) does so when it meets an
+    /// unknown address, but walks that neither allocate nor take locks, such as
+    /// [`capture_into`](Self::capture_into), don't. Call this after loading a library to
+    /// unwind through it with those.
+    #[cfg(target_os = "linux")]
+    pub fn refresh_modules(&mut self) {
+        self.use_snapshot_modules(false);
+        self.modules.refresh(&mut self.unwinder);
+    }
+
+    /// Copies the registers and up to `max_stack_bytes` of the stack of the calling thread, to
+    /// be unwound later with [`unwind_snapshot`](Self::unwind_snapshot). The first frame of the
+    /// snapshot is the one of this method.
+    pub fn snapshot(&mut self, max_stack_bytes: usize) -> StackSnapshot {
+        let Regs { pc, sp, fp, lr } = current_regs!();
+        #[cfg(target_os = "linux")]
+        self.use_snapshot_modules(false);
+        let stack = self.stack.reader().copy_stack(sp, max_stack_bytes);
+        StackSnapshot {
+            pc,
+            sp,
+            fp,
+            lr,
+            stack_base: sp,
+            stack,
+            #[cfg(target_os = "linux")]
+            modules: self.modules.snapshot_modules(),
+            #[cfg(not(target_os = "linux"))]
+            modules: Vec::new(),
+        }
+    }
+
+    /// Unwinds a stack copied with [`snapshot`](Self::snapshot) or by other means, with the
+    /// modules of the snapshot. The walk ends where the copy of the stack ends.
+    pub fn unwind_snapshot<'a>(
+        &'a mut self,
+        snapshot: &'a StackSnapshot,
+    ) -> LiveUnwindIterator<'a, A> {
+        #[cfg(target_os = "linux")]
+        if snapshot.modules.is_empty() {
+            self.use_snapshot_modules(false);
+        } else {
+            self.use_snapshot_modules(true);
+            let images = snapshot.modules.iter().map(Into::into).collect();
+            self.modules.refresh_from(images, &mut self.unwinder);
+        }
+        let reader = StackReader::Copied {
+            base: snapshot.stack_base,
+            bytes: &snapshot.stack,
+        };
+        let regs = Regs {
+            pc: snapshot.pc,
+            sp: snapshot.sp,
+            fp: snapshot.fp,
+            lr: snapshot.lr,
+        };
+        let skip_frames = self.options.skip_frames;
+        // The images loaded now are not the ones of the snapshot.
+        self.walk_without_refresh(reader, regs, skip_frames)
+    }
+
+    /// Returns the module with the given [index](Frame::module_index), or `None` if it has
+    /// been unloaded since. After [`unwind_snapshot`](Self::unwind_snapshot), these are the
+    /// modules of the snapshot until the next walk of this process.
+    #[cfg(target_os = "linux")]
+    pub fn module(&self, index: usize) -> Option<super::SnapshotModule> {
+        self.modules.get(index).map(Into::into)
+    }
+
+    /// Registers the code in `start..end`, e.g. generated by a JIT, so that walks can pass
+    /// through it. The range is kept until it is unregistered, even if the loaded images are
+    /// re-read.
+    ///
+    /// `name` is reported as the path of the module of the range, see
+    /// [`module`](Self::module), so that frames in it can be named.
+    ///
+    /// # Safety
+    ///
+    /// Walks read the code of the range, e.g. to recognize signal trampolines and call
+    /// instructions, so `start..end` must stay mapped and readable until it is unregistered
+    /// with [`unregister_code_range`](Self::unregister_code_range) or the unwinder is dropped.
+    #[cfg(target_os = "linux")]
+    pub unsafe fn register_code_range(
+        &mut self,
+        start: u64,
+        end: u64,
+        unwind_info: super::CodeUnwindInfo,
+        name: Option<&str>,
+    ) {
+        self.use_snapshot_modules(false);
+        self.modules
+            .register_code_range(start..end, unwind_info, name, &mut self.unwinder);
+    }
+
+    /// Forgets the code range registered at `start`, e.g. before the JIT frees the code.
+    /// Returns whether there was one.
+    #[cfg(target_os = "linux")]
+    pub fn unregister_code_range(&mut self, start: u64) -> bool {
+        self.use_snapshot_modules(false);
+        self.modules
+            .unregister_code_range(start, &mut self.unwinder)
+    }
+
+    /// Walks the stack of the calling thread from `regs`, dropping `internal_frames` more
+    /// frames than configured.
+    pub(super) fn iter_frames(
+        &mut self,
+        regs: Regs,
+        internal_frames: usize,
+    ) -> LiveUnwindIterator<'_, A> {
+        #[cfg(target_os = "linux")]
+        self.use_snapshot_modules(false);
+        let reader = self.stack.reader();
+        let skip_frames = self.options.skip_frames + internal_frames;
+        LiveUnwindIterator::new(self, reader, regs, skip_frames)
+    }
+
+    /// Walks the stack of a fiber from `regs`, see
+    /// [`unwind_fiber`](LiveStackUnwinder::unwind_fiber).
+    ///
+    /// # Safety
+    ///
+    /// `stack` must be mapped, readable memory until the returned iterator is dropped.
+    pub(super) unsafe fn fiber(
+        &mut self,
+        regs: Regs,
+        stack: StackBounds,
+    ) -> LiveUnwindIterator<'_, A> {
+        #[cfg(target_os = "linux")]
+        self.use_snapshot_modules(false);
+        let skip_frames = self.options.skip_frames;
+        LiveUnwindIterator::new(self, StackReader::new(Some(stack)), regs, skip_frames)
+    }
+
+    /// Starts a walk that doesn't re-read the loaded images when it meets an unknown address,
+    /// since that allocates and takes the dynamic linker's lock.
+    fn walk_without_refresh<'a>(
+        &'a mut self,
+        reader: StackReader<'a>,
+        regs: Regs,
+        skip_frames: usize,
+    ) -> LiveUnwindIterator<'a, A> {
+        #[allow(unused_mut)]
+        let mut iter = LiveUnwindIterator::new(self, reader, regs, skip_frames);
+        #[cfg(target_os = "linux")]
+        {
+            iter.modules_refreshed = true;
+        }
+        iter
+    }
+
+    /// Switches between the modules of this process and the ones of the last unwound
+    /// snapshot, so that unwinding a snapshot doesn't replace the modules used by
+    /// [`capture_into`](Self::capture_into), which can't re-read them. Switching neither
+    /// allocates nor takes locks.
+    #[cfg(target_os = "linux")]
+    fn use_snapshot_modules(&mut self, snapshot: bool) {
+        if self.snapshot_modules_active == snapshot {
+            return;
+        }
+        std::mem::swap(&mut self.cache, &mut self.other_modules.cache);
+        std::mem::swap(&mut self.unwinder, &mut self.other_modules.unwinder);
+        std::mem::swap(&mut self.modules, &mut self.other_modules.modules);
+        self.snapshot_modules_active = snapshot;
+    }
+
+    /// Re-reads the loaded images if `address` is not covered by any known module, e.g.
+    /// because a library has been loaded with `dlopen` since the last refresh.
+    ///
+    /// Returns whether the modules were refreshed.
+    #[cfg(target_os = "linux")]
+    fn refresh_modules_for(&mut self, address: u64) -> bool {
+        if self.modules.find(address).is_some() {
+            return false;
+        }
+        self.modules.refresh(&mut self.unwinder);
+        true
+    }
+
+    /// Returns the [index](Frame::module_index) of the module containing `address`.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn module_index(&self, address: u64) -> Option<usize> {
+        #[cfg(target_os = "linux")]
+        return self.modules.find(address).map(|image| image.index);
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+
+    /// Explains why unwinding the frame at `address` failed with `error`.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn termination_reason(
+        &self,
+        address: FrameAddress,
+        error: framehop::Error,
+    ) -> TerminationReason {
+        #[cfg(target_os = "linux")]
+        return self.modules.termination_reason(address, error);
+        #[cfg(not(target_os = "linux"))]
+        error.into()
+    }
+
+    fn regs(&self, regs: Regs) -> A::Regs {
+        A::regs(self.config, regs.pc, regs.sp, regs.fp, regs.lr)
+    }
+}
+
+enum UnwindIteratorState {
+    Initial(u64),
+    Unwinding(FrameAddress),
+    Done(TerminationReason),
+}
+
+pub struct LiveUnwindIterator<'a, A: LiveArch> {
+    unwinder: &'a mut LiveStackUnwinder<A>,
+    reader: StackReader<'a>,
+    regs: A::Regs,
+    state: UnwindIteratorState,
+    /// Number of frames still to be dropped.
+    skip_frames: usize,
+    /// Number of frames still to be yielded, if limited.
+    remaining_frames: Option<usize>,
+    /// The modules are refreshed at most once per walk.
+    #[cfg(target_os = "linux")]
+    modules_refreshed: bool,
+    /// The kind of the next frame, if it isn't implied by its address.
+    #[cfg(target_os = "linux")]
+    next_kind: Option<FrameKind>,
+}
+
+impl<'a, A: LiveArch> LiveUnwindIterator<'a, A> {
+    fn new(
+        unwinder: &'a mut LiveStackUnwinder<A>,
+        reader: StackReader<'a>,
+        regs: Regs,
+        skip_frames: usize,
+    ) -> Self {
+        let remaining_frames = unwinder.options.max_frames;
+        Self {
+            regs: unwinder.regs(regs),
+            unwinder,
+            reader,
+            state: UnwindIteratorState::Initial(regs.pc),
+            skip_frames,
+            remaining_frames,
+            #[cfg(target_os = "linux")]
+            modules_refreshed: false,
+            #[cfg(target_os = "linux")]
+            next_kind: None,
+        }
+    }
+
+    /// Returns why the walk ended, or `None` if it has not ended yet.
+    pub fn termination_reason(&self) -> Option<&TerminationReason> {
+        match &self.state {
+            UnwindIteratorState::Done(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Unwinds the frame at `address`, returning the address of its caller.
+    fn unwind_frame(&mut self, address: FrameAddress) -> Result<FrameAddress, TerminationReason> {
+        #[cfg(target_os = "linux")]
+        if let Some(result) = self.unwind_signal_frame(address) {
+            return result;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(result) = self.unwind_frame_setup(address) {
+            return result;
+        }
+
+        let LiveStackUnwinder {
+            cache,
+            unwinder,
+            config,
+            ..
+        } = &mut *self.unwinder;
+        #[cfg(target_os = "linux")]
+        let regs = self.regs;
+        let reader = &self.reader;
+        let mut read_stack = |address| reader.read_u64(address);
+        let result = match unwinder.unwind_frame(address, &mut self.regs, cache, &mut read_stack) {
+            // framehop doesn't strip the return addresses it gets from DWARF CFI.
+            Ok(Some(return_address)) => {
+                FrameAddress::from_return_address(A::strip_return_address(*config, return_address))
+                    .ok_or(framehop::Error::ReturnAddressIsNull)
+            }
+            Ok(None) => return Err(TerminationReason::EndOfStack),
+            Err(error) => Err(error),
+        };
+        let error = match result {
+            Ok(address) => return Ok(address),
+            Err(error) => error,
+        };
+        #[cfg(target_os = "linux")]
+        if let Some(address) = self.scan_stack(regs) {
+            return Ok(address);
+        }
+        Err(self.unwinder.termination_reason(address, error))
+    }
+
+    /// Looks for the return address of the frame with the registers `regs` by scanning the
+    /// stack, if enabled, and continues in the caller with the stack pointer past it.
+    ///
+    /// The frame pointer of the caller is taken from the word before the return address if
+    /// they form a frame record. Where that isn't always the case, this is only done if the
+    /// frame pointer was clobbered, i.e. doesn't point into the stack above the return
+    /// address, and the word does.
+    #[cfg(target_os = "linux")]
+    fn scan_stack(&mut self, mut regs: A::Regs) -> Option<FrameAddress> {
+        if !self.unwinder.options.stack_scanning {
+            return None;
+        }
+        let config = self.unwinder.config;
+        let sp = A::sp(&regs);
+        let (slot, return_address) = (0..scan::SCAN_WORDS)
+            .map(|word| sp + word * 8)
+            .map_while(|slot| {
+                let value = self.reader.read_u64(slot).ok()?;
+                Some((slot, A::strip_return_address(config, value)))
+            })
+            .find(|&(_, value)| self.unwinder.modules.follows_call(value))?;
+        if slot > sp {
+            if let Ok(saved_fp) = self.reader.read_u64(slot - 8) {
+                let points_above = |fp: u64| fp > slot && self.reader.read_u64(fp).is_ok();
+                if A::SCANNED_RETURN_ADDRESS_IN_FRAME_RECORD
+                    || (!points_above(A::fp(&regs)) && points_above(saved_fp))
+                {
+                    A::set_fp(&mut regs, saved_fp);
+                }
+            }
+        }
+        A::set_sp(&mut regs, slot + 8);
+        self.regs = regs;
+        self.next_kind = Some(FrameKind::StackScanned);
+        FrameAddress::from_return_address(return_address)
+    }
+
+    /// Unwinds the first frame of the walk if it is in the prologue or the epilogue of a
+    /// function without CFI, where the frame pointer still belongs to the caller.
+    ///
+    /// Like for signal frames, only the code of the stack being walked live is checked.
+    #[cfg(target_os = "linux")]
+    fn unwind_frame_setup(
+        &mut self,
+        address: FrameAddress,
+    ) -> Option<Result<FrameAddress, TerminationReason>> {
+        let FrameAddress::InstructionPointer(pc) = address else {
+            return None;
+        };
+        if !matches!(self.reader, StackReader::Live { .. }) {
+            return None;
+        }
+        let setup = self.unwinder.modules.frame_setup(pc)?;
+        let reader = &self.reader;
+        let mut read_stack = |address| reader.read_u64(address);
+        let sp = A::sp(&self.regs);
+        let caller = match setup {
+            FrameSetup::NotStarted => A::caller_before_frame_setup(&self.regs, &mut read_stack)
+                .map(|(return_address, caller_sp)| (return_address, caller_sp, A::fp(&self.regs))),
+            FrameSetup::RecordSaved { frame_size } => read_stack(sp + 8)
+                .map_err(|_| sp + 8)
+                .and_then(|return_address| {
+                    let caller_fp = read_stack(sp).map_err(|_| sp)?;
+                    Ok((return_address, sp + frame_size, caller_fp))
+                }),
+        };
+        let (return_address, caller_sp, caller_fp) = match caller {
+            Ok(caller) => caller,
+            Err(address) => return Some(Err(TerminationReason::MemoryReadFailed { address })),
+        };
+        A::set_sp(&mut self.regs, caller_sp);
+        A::set_fp(&mut self.regs, caller_fp);
+        let return_address = A::strip_return_address(self.unwinder.config, return_address);
+        Some(
+            FrameAddress::from_return_address(return_address)
+                .ok_or_else(|| framehop::Error::ReturnAddressIsNull.into()),
+        )
+    }
+
+    /// Unwinds the frame at `address` if it is the trampoline a signal handler returns to,
+    /// continuing with the registers saved in the signal frame.
+    ///
+    /// Only the code of the stack being walked live is checked, since the code of a snapshot
+    /// may not be mapped into this process anymore.
+    #[cfg(target_os = "linux")]
+    fn unwind_signal_frame(
+        &mut self,
+        address: FrameAddress,
+    ) -> Option<Result<FrameAddress, TerminationReason>> {
+        if !matches!(self.reader, StackReader::Live { .. })
+            || !self
+                .unwinder
+                .modules
+                .is_signal_trampoline(address.address())
+        {
+            return None;
+        }
+        let reader = &self.reader;
+        let regs = match signal::saved_regs(A::sp(&self.regs), |address| reader.read_u64(address)) {
+            Ok(regs) => regs,
+            Err(address) => return Some(Err(TerminationReason::MemoryReadFailed { address })),
+        };
+        if regs.pc == 0 {
+            return Some(Err(framehop::Error::ReturnAddressIsNull.into()));
+        }
+        self.regs = self.unwinder.regs(regs);
+        self.next_kind = Some(FrameKind::InterruptedBySignal);
+        Some(Ok(FrameAddress::InstructionPointer(regs.pc)))
+    }
+
+    /// Walks to the next frame, regardless of the frames to skip or the depth limit.
+    fn next_frame(&mut self) -> Option<Frame> {
+        let address = match &self.state {
+            UnwindIteratorState::Initial(pc) => FrameAddress::InstructionPointer(*pc),
+            UnwindIteratorState::Unwinding(address) => {
+                let address = *address;
+                match self.unwind_frame(address) {
+                    Ok(address) => address,
+                    Err(reason) => {
+                        self.state = UnwindIteratorState::Done(reason);
+                        return None;
+                    }
+                }
+            }
+            UnwindIteratorState::Done(_) => return None,
+        };
+        self.state = UnwindIteratorState::Unwinding(address);
+
+        #[cfg(target_os = "linux")]
+        if !self.modules_refreshed {
+            self.modules_refreshed = self
+                .unwinder
+                .refresh_modules_for(address.address_for_lookup());
+        }
+        let module_index = self.unwinder.module_index(address.address_for_lookup());
+        let frame = Frame::new(address, A::sp(&self.regs), A::fp(&self.regs), module_index);
+        #[cfg(target_os = "linux")]
+        if let Some(kind) = self.next_kind.take() {
+            return Some(frame.with_kind(kind));
+        }
+        Some(frame)
+    }
+}
+
+impl<A: LiveArch> Iterator for LiveUnwindIterator<'_, A> {
+    type Item = Frame;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.remaining_frames == Some(0) {
+                if self.termination_reason().is_none() {
+                    self.state = UnwindIteratorState::Done(TerminationReason::DepthLimit);
+                }
+                return None;
+            }
+            let frame = self.next_frame()?;
+            if self.skip_frames > 0 {
+                self.skip_frames -= 1;
+                continue;
+            }
+            if let Some(remaining_frames) = &mut self.remaining_frames {
+                *remaining_frames -= 1;
+            }
+            return Some(frame);
+        }
+    }
+}
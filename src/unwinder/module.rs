@@ -0,0 +1,729 @@
+//! Discovery of the unwind information of the images loaded into this process.
+
+use super::{prologue, scan, signal, ModuleData, SnapshotModule, TerminationReason};
+use crate::vdso;
+use framehop::{ExplicitModuleSectionInfo, FrameAddress, Module, Unwinder};
+use object::{Object, ObjectSection, ObjectSegment};
+use std::{
+    borrow::Cow,
+    ffi::{c_int, c_void, CStr},
+    io,
+    ops::Range,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// The path of code ranges registered without a name.
+const JIT_PATH: &str = "[jit]";
+
+/// How to unwind the code of a range registered with
+/// [`register_code_range`](super::StackUnwinder::register_code_range).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum CodeUnwindInfo {
+    /// DWARF CFI in the `.eh_frame` format. `address` is where `data` is located in memory,
+    /// to resolve pc-relative pointers; it doesn't matter if the FDEs hold absolute
+    /// addresses.
+    EhFrame { data: Vec<u8>, address: u64 },
+    /// The code keeps a frame pointer in each function, and the return address next to the
+    /// saved frame pointer of the caller.
+    FramePointer,
+}
+
+/// An ELF image mapped into a process.
+pub(crate) struct LoadedImage {
+    /// Path of the image.
+    pub(crate) path: PathBuf,
+    /// Difference between the addresses in the process and the addresses stated in the file.
+    pub(crate) bias: u64,
+    /// Address range covered by the `PT_LOAD` segments of the image.
+    pub(crate) avma_range: Range<u64>,
+    /// The executable segments, if the image is mapped into this process. Empty for the
+    /// images of other processes or snapshots, whose code can't be read directly.
+    pub(crate) code_ranges: Vec<Range<u64>>,
+    /// The unwind sections in memory, if the image is mapped into this process and has a
+    /// `PT_GNU_EH_FRAME` segment.
+    pub(crate) eh_frame: Option<EhFrameRanges>,
+}
+
+/// Where the `.eh_frame_hdr` and `.eh_frame` sections of an image are mapped.
+pub(crate) struct EhFrameRanges {
+    pub(crate) eh_frame_hdr: Range<u64>,
+    /// The size of `.eh_frame` isn't recorded in memory, so this extends to the end of its
+    /// segment.
+    pub(crate) eh_frame: Range<u64>,
+}
+
+impl From<&RegisteredImage> for SnapshotModule {
+    fn from(image: &RegisteredImage) -> Self {
+        Self {
+            path: image.path.clone(),
+            bias: image.bias,
+            avma_range: image.avma_range.clone(),
+        }
+    }
+}
+
+impl From<&SnapshotModule> for LoadedImage {
+    fn from(module: &SnapshotModule) -> Self {
+        Self {
+            path: module.path.to_path_buf(),
+            bias: module.bias,
+            avma_range: module.avma_range.clone(),
+            code_ranges: Vec::new(),
+            eh_frame: None,
+        }
+    }
+}
+
+/// Returns every image currently loaded into this process, the main executable first.
+///
+/// With `from_memory`, the file system is not touched, not even to find the path of the main
+/// executable.
+pub(crate) fn loaded_images(from_memory: bool) -> Vec<LoadedImage> {
+    unsafe extern "C" fn callback(
+        info: *mut libc::dl_phdr_info,
+        _size: usize,
+        data: *mut c_void,
+    ) -> c_int {
+        let (images, from_memory) = &mut *(data as *mut (Vec<LoadedImage>, bool));
+        let is_main_executable = images.is_empty();
+        if let Some(image) = loaded_image(&*info, is_main_executable, *from_memory) {
+            images.push(image);
+        }
+        0
+    }
+
+    let mut data = (Vec::new(), from_memory);
+    unsafe { libc::dl_iterate_phdr(Some(callback), &mut data as *mut _ as *mut c_void) };
+    data.0
+}
+
+fn loaded_image(
+    info: &libc::dl_phdr_info,
+    is_main_executable: bool,
+    from_memory: bool,
+) -> Option<LoadedImage> {
+    let phdrs = unsafe { std::slice::from_raw_parts(info.dlpi_phdr, info.dlpi_phnum as usize) };
+    let (start, end) = phdrs
+        .iter()
+        .filter(|phdr| phdr.p_type == libc::PT_LOAD)
+        .fold((u64::MAX, 0), |(start, end), phdr| {
+            (
+                start.min(phdr.p_vaddr),
+                end.max(phdr.p_vaddr + phdr.p_memsz),
+            )
+        });
+    if start >= end {
+        return None;
+    }
+
+    let name = if info.dlpi_name.is_null() {
+        Default::default()
+    } else {
+        unsafe { CStr::from_ptr(info.dlpi_name) }.to_string_lossy()
+    };
+    let bias = info.dlpi_addr;
+    let avma_range = bias.wrapping_add(start)..bias.wrapping_add(end);
+    // The dynamic linker reports the main executable first, with an empty name.
+    let path = if avma_range.contains(&vdso::base()) {
+        PathBuf::from(vdso::PATH)
+    } else if is_main_executable && name.is_empty() && !from_memory {
+        std::fs::read_link("/proc/self/exe").unwrap_or_else(|_| "/proc/self/exe".into())
+    } else if is_main_executable && name.is_empty() {
+        PathBuf::from("/proc/self/exe")
+    } else {
+        PathBuf::from(&*name)
+    };
+    let code_ranges = phdrs
+        .iter()
+        .filter(|phdr| {
+            phdr.p_type == libc::PT_LOAD
+                && phdr.p_flags & libc::PF_X != 0
+                && phdr.p_flags & libc::PF_R != 0
+        })
+        .map(|phdr| {
+            let start = bias.wrapping_add(phdr.p_vaddr);
+            start..start + phdr.p_memsz
+        })
+        .collect();
+    let eh_frame = phdrs
+        .iter()
+        .find(|phdr| phdr.p_type == libc::PT_GNU_EH_FRAME)
+        .and_then(|phdr| {
+            let start = bias.wrapping_add(phdr.p_vaddr);
+            eh_frame_ranges(start..start + phdr.p_memsz, phdrs, bias)
+        });
+    Some(LoadedImage {
+        path,
+        bias,
+        avma_range,
+        code_ranges,
+        eh_frame,
+    })
+}
+
+/// Locates `.eh_frame` from the `.eh_frame_hdr` section mapped at `eh_frame_hdr`.
+fn eh_frame_ranges(
+    eh_frame_hdr: Range<u64>,
+    phdrs: &[libc::Elf64_Phdr],
+    bias: u64,
+) -> Option<EhFrameRanges> {
+    // The header starts with a version, the encoding of `eh_frame_ptr`, then the encodings of
+    // the search table, which framehop decodes.
+    const HEADER_LEN: u64 = 4;
+    const DW_EH_PE_PCREL: u8 = 0x10;
+    if eh_frame_hdr.end - eh_frame_hdr.start < HEADER_LEN + 8 {
+        return None;
+    }
+    let header = unsafe { std::slice::from_raw_parts(eh_frame_hdr.start as *const u8, 12) };
+    let (version, encoding) = (header[0], header[1]);
+    if version != 1 {
+        return None;
+    }
+    let field = eh_frame_hdr.start + HEADER_LEN;
+    let value = match encoding & 0x0f {
+        // `udata4`, `sdata4`.
+        0x03 => u64::from(u32::from_le_bytes(header[4..8].try_into().unwrap())),
+        0x0b => i32::from_le_bytes(header[4..8].try_into().unwrap()) as u64,
+        // `absptr`, `udata8`, `sdata8`.
+        0x00 | 0x04 | 0x0c => u64::from_le_bytes(header[4..12].try_into().unwrap()),
+        _ => return None,
+    };
+    let start = match encoding & 0x70 {
+        0 => value,
+        DW_EH_PE_PCREL => field.wrapping_add(value),
+        _ => return None,
+    };
+    let segment = phdrs
+        .iter()
+        .filter(|phdr| phdr.p_type == libc::PT_LOAD && phdr.p_flags & libc::PF_R != 0)
+        .map(|phdr| {
+            let segment_start = bias.wrapping_add(phdr.p_vaddr);
+            segment_start..segment_start + phdr.p_memsz
+        })
+        .find(|segment| segment.contains(&start))?;
+    Some(EhFrameRanges {
+        eh_frame_hdr,
+        eh_frame: start..segment.end,
+    })
+}
+
+/// Returns the ELF images mapped into the process `pid`, according to `/proc/<pid>/maps`.
+pub(crate) fn remote_images(pid: libc::pid_t) -> io::Result<Vec<LoadedImage>> {
+    let maps = std::fs::read_to_string(format!("/proc/{pid}/maps"))?;
+    // The lowest mapping of each file, with its file offset.
+    let mut first_mappings: Vec<(&str, u64, u64)> = Vec::new();
+    for line in maps.lines() {
+        // start-end perms offset dev inode path
+        let fields: Vec<&str> = line.splitn(6, ' ').collect();
+        let [range, _, offset, _, _, path] = fields[..] else {
+            continue;
+        };
+        let path = path.trim_start();
+        // Anonymous mappings, the stack, etc.
+        if !(path.starts_with('/') || path == vdso::PATH)
+            || first_mappings.iter().any(|(known, ..)| *known == path)
+        {
+            continue;
+        }
+        let Some((start, _)) = range.split_once('-') else {
+            continue;
+        };
+        if let (Ok(start), Ok(offset)) = (
+            u64::from_str_radix(start, 16),
+            u64::from_str_radix(offset, 16),
+        ) {
+            first_mappings.push((path, start, offset));
+        }
+    }
+    Ok(first_mappings
+        .into_iter()
+        .filter_map(|(path, start, offset)| remote_image(path, start, offset))
+        .collect())
+}
+
+/// Locates the image at `path`, whose file `offset` is mapped at `start`.
+fn remote_image(path: &str, start: u64, offset: u64) -> Option<LoadedImage> {
+    let data = image_data(Path::new(path))?;
+    let file = object::File::parse(&*data).ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+
+    // Segments are mapped from page boundaries, so `offset` may precede the segment.
+    let segment = file.segments().find(|segment| {
+        let (segment_offset, file_size) = segment.file_range();
+        segment_offset - segment_offset % page_size <= offset
+            && offset < segment_offset + file_size.max(1)
+    })?;
+    let (segment_offset, _) = segment.file_range();
+    let bias = start
+        .wrapping_add(segment_offset)
+        .wrapping_sub(offset)
+        .wrapping_sub(segment.address());
+
+    let (svma_start, svma_end) = file
+        .segments()
+        .fold((u64::MAX, 0), |(start, end), segment| {
+            (
+                start.min(segment.address()),
+                end.max(segment.address() + segment.size()),
+            )
+        });
+    if svma_start >= svma_end {
+        return None;
+    }
+    Some(LoadedImage {
+        path: PathBuf::from(path),
+        bias,
+        avma_range: bias.wrapping_add(svma_start)..bias.wrapping_add(svma_end),
+        code_ranges: Vec::new(),
+        eh_frame: None,
+    })
+}
+
+/// The unwind sections of the images registered so far, shared by every unwinder of the
+/// process so that each file is only read once.
+static SECTIONS: Mutex<Vec<CachedSections>> = Mutex::new(Vec::new());
+
+struct CachedSections {
+    path: PathBuf,
+    avma_range: Range<u64>,
+    /// The file the sections were read from, since another one may be mapped at the same
+    /// path and address later, e.g. after an upgrade or in another process.
+    file: Option<FileIdentity>,
+    /// `None` if the image has no usable unwind information.
+    sections: Option<ExplicitModuleSectionInfo<ModuleData>>,
+}
+
+/// Identifies a file and its contents, independently of its path.
+#[derive(PartialEq, Eq)]
+struct FileIdentity {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileIdentity {
+    /// Returns `None` if there is no file at `path`, e.g. for the vDSO.
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        })
+    }
+}
+
+/// Builds a framehop module for `image` from the sections of its ELF file.
+///
+/// Returns `None` if the file can't be read or parsed, or if it has no unwind information.
+pub(crate) fn load_module(image: &LoadedImage) -> Option<Module<ModuleData>> {
+    let section_info = {
+        let file = FileIdentity::of(&image.path);
+        let mut cache = SECTIONS.lock().unwrap_or_else(PoisonError::into_inner);
+        let cached = cache.iter().find(|cached| {
+            cached.path == image.path
+                && cached.avma_range == image.avma_range
+                && cached.file == file
+        });
+        match cached {
+            Some(cached) => cached.sections.clone(),
+            None => {
+                let sections = read_sections(&image.path);
+                cache.push(CachedSections {
+                    path: image.path.clone(),
+                    avma_range: image.avma_range.clone(),
+                    file,
+                    sections: sections.clone(),
+                });
+                sections
+            }
+        }
+    }?;
+
+    Some(Module::new(
+        image.path.to_string_lossy().into_owned(),
+        image.avma_range.clone(),
+        image.bias,
+        section_info,
+    ))
+}
+
+/// Builds a framehop module for `image` from its unwind sections in memory, without reading
+/// its file.
+///
+/// Returns `None` if the image is not mapped into this process or has no `PT_GNU_EH_FRAME`
+/// segment.
+fn memory_module(image: &LoadedImage) -> Option<Module<ModuleData>> {
+    let EhFrameRanges {
+        eh_frame_hdr,
+        eh_frame,
+    } = image.eh_frame.as_ref()?;
+    // The sections are copied, since the image may be unloaded while the module is in use.
+    let copy = |range: &Range<u64>| {
+        let len = (range.end - range.start) as usize;
+        ModuleData::from(unsafe { std::slice::from_raw_parts(range.start as *const u8, len) })
+    };
+    let svma = |range: &Range<u64>| {
+        range.start.wrapping_sub(image.bias)..range.end.wrapping_sub(image.bias)
+    };
+    let section_info = ExplicitModuleSectionInfo {
+        base_svma: 0,
+        text_svma: image.code_ranges.first().map(svma),
+        eh_frame_svma: Some(svma(eh_frame)),
+        eh_frame_hdr_svma: Some(svma(eh_frame_hdr)),
+        eh_frame_hdr: Some(copy(eh_frame_hdr)),
+        eh_frame: Some(copy(eh_frame)),
+        ..Default::default()
+    };
+    Some(Module::new(
+        image.path.to_string_lossy().into_owned(),
+        image.avma_range.clone(),
+        image.bias,
+        section_info,
+    ))
+}
+
+/// Forgets the cached sections of the images that are no longer in `loaded`.
+fn evict_sections(loaded: &[LoadedImage]) {
+    let mut cache = SECTIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    cache.retain(|cached| {
+        loaded
+            .iter()
+            .any(|image| cached.path == image.path && cached.avma_range == image.avma_range)
+    });
+}
+
+/// Returns the contents of the image at `path`. The vDSO has no file, but is the same in
+/// every process, so the one of this process is used.
+fn image_data(path: &Path) -> Option<Cow<'static, [u8]>> {
+    if path == Path::new(vdso::PATH) {
+        return vdso::image().map(Cow::Borrowed);
+    }
+    std::fs::read(path).ok().map(Cow::Owned)
+}
+
+fn read_sections(path: &Path) -> Option<ExplicitModuleSectionInfo<ModuleData>> {
+    let data = image_data(path)?;
+    let file = object::File::parse(&*data).ok()?;
+
+    let section_svma = |name: &str| {
+        file.section_by_name(name)
+            .map(|section| section.address()..section.address() + section.size())
+    };
+    let section_data = |name: &str| {
+        file.section_by_name(name)
+            .and_then(|section| section.data().ok())
+            .map(ModuleData::from)
+    };
+
+    let eh_frame = section_data(".eh_frame");
+    // `.debug_frame` is only consulted when there is no `.eh_frame`.
+    let debug_frame = eh_frame
+        .is_none()
+        .then(|| section_data(".debug_frame"))
+        .flatten();
+    if eh_frame.is_none() && debug_frame.is_none() {
+        return None;
+    }
+    Some(ExplicitModuleSectionInfo {
+        base_svma: 0,
+        text_svma: section_svma(".text"),
+        got_svma: section_svma(".got"),
+        eh_frame_svma: section_svma(".eh_frame"),
+        eh_frame_hdr_svma: section_svma(".eh_frame_hdr"),
+        eh_frame_hdr: section_data(".eh_frame_hdr"),
+        eh_frame,
+        debug_frame,
+        ..Default::default()
+    })
+}
+
+/// An image known to the unwinder.
+pub(crate) struct RegisteredImage {
+    pub(crate) path: Arc<Path>,
+    pub(crate) bias: u64,
+    pub(crate) avma_range: Range<u64>,
+    /// Stays the same while the image is loaded, unlike its position in `Modules::images`.
+    pub(crate) index: usize,
+    /// See [`LoadedImage::code_ranges`].
+    pub(crate) code_ranges: Vec<Range<u64>>,
+    /// Whether the image was registered with `register_code_range`, e.g. code generated by a
+    /// JIT, rather than loaded. Such images are kept until they are unregistered.
+    pub(crate) jit: bool,
+    /// Whether a module with DWARF CFI has been handed to the unwinder. Otherwise, the image
+    /// is unwound with frame pointers.
+    pub(crate) has_cfi: bool,
+}
+
+/// Reads the code at `address` in this process.
+///
+/// The image of the code may have been unloaded since the modules were last refreshed, e.g.
+/// by `dlclose`, so the code is read with `process_vm_readv`, which fails instead of
+/// crashing if it is no longer mapped.
+fn read_code<const N: usize>(address: u64) -> Option<[u8; N]> {
+    let mut code = [0; N];
+    let local = libc::iovec {
+        iov_base: code.as_mut_ptr().cast(),
+        iov_len: N,
+    };
+    let remote = libc::iovec {
+        iov_base: address as *mut c_void,
+        iov_len: N,
+    };
+    let read = unsafe { libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0) };
+    (read == N as isize).then_some(code)
+}
+
+impl RegisteredImage {
+    /// Whether `range` lies within one of the executable segments, which were mapped when the
+    /// image was registered.
+    fn contains_code(&self, range: Range<u64>) -> bool {
+        self.code_ranges
+            .iter()
+            .any(|code| code.start <= range.start && range.end <= code.end)
+    }
+}
+
+/// Keeps the modules of an unwinder in sync with the images loaded into this process.
+#[derive(Default)]
+pub(crate) struct Modules {
+    /// Sorted by `avma_range.start`.
+    images: Vec<RegisteredImage>,
+    /// The index of the next image to be registered.
+    next_index: usize,
+    /// Whether the images loaded into this process are registered from their unwind sections
+    /// in memory rather than from their files.
+    from_memory: bool,
+}
+
+impl Modules {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like [`new`](Self::new), but the images loaded into this process are registered without
+    /// reading their files, see [`memory_module`].
+    pub(crate) fn from_memory() -> Self {
+        Self {
+            from_memory: true,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the known image containing `address`.
+    pub(crate) fn find(&self, address: u64) -> Option<&RegisteredImage> {
+        let index = self
+            .images
+            .partition_point(|image| image.avma_range.start <= address);
+        let image = self.images.get(index.checked_sub(1)?)?;
+        image.avma_range.contains(&address).then_some(image)
+    }
+
+    /// Returns the known image with the given [`index`](RegisteredImage::index).
+    pub(crate) fn get(&self, index: usize) -> Option<&RegisteredImage> {
+        self.images.iter().find(|image| image.index == index)
+    }
+
+    /// Whether `address` is the start of the trampoline a signal handler returns to. Only the
+    /// code of images mapped into this process is read.
+    pub(crate) fn is_signal_trampoline(&self, address: u64) -> bool {
+        let Some(image) = self.find(address) else {
+            return false;
+        };
+        if !image.contains_code(address..address + signal::TRAMPOLINE_READ_LEN) {
+            return false;
+        }
+        signal::is_trampoline(address, |address| {
+            read_code(address).map(u64::from_le_bytes).ok_or(())
+        })
+    }
+
+    /// Whether `address` follows a call instruction, as a return address does. Only the code
+    /// of images mapped into this process is read.
+    pub(crate) fn follows_call(&self, address: u64) -> bool {
+        let (Some(image), Some(start)) =
+            (self.find(address), address.checked_sub(scan::CALL_READ_LEN))
+        else {
+            return false;
+        };
+        if !image.contains_code(start..address) {
+            return false;
+        }
+        read_code(start).is_some_and(scan::ends_with_call)
+    }
+
+    /// The state of the frame at `pc`, the first frame of a walk, if it is in the prologue or
+    /// the epilogue of a function without CFI. Only the code of images mapped into this
+    /// process is read.
+    pub(crate) fn frame_setup(&self, pc: u64) -> Option<prologue::FrameSetup> {
+        let image = self.find(pc)?;
+        let start = pc.checked_sub(prologue::CODE_BEFORE)?;
+        if image.has_cfi || !image.contains_code(start..pc + prologue::CODE_AFTER) {
+            return None;
+        }
+        prologue::frame_setup(pc, read_code(start)?)
+    }
+
+    /// Lists the known images, to be stored in a snapshot.
+    pub(crate) fn snapshot_modules(&self) -> Vec<SnapshotModule> {
+        self.images.iter().map(SnapshotModule::from).collect()
+    }
+
+    /// Explains why unwinding the frame at `address` failed with `error`.
+    pub(crate) fn termination_reason(
+        &self,
+        address: FrameAddress,
+        error: framehop::Error,
+    ) -> TerminationReason {
+        // Without unwind information, framehop falls back to frame pointers, which is the
+        // most likely reason for the failure. Registered code ranges without CFI are meant
+        // to be unwound with frame pointers.
+        let address = address.address_for_lookup();
+        match self.find(address) {
+            Some(image) if image.has_cfi || image.jit => error.into(),
+            image => TerminationReason::MissingUnwindInfo {
+                address,
+                module: image.map(|image| image.path.clone()),
+            },
+        }
+    }
+
+    /// Registers the code in `avma_range` with `unwinder`, replacing any range registered at
+    /// the same address.
+    pub(crate) fn register_code_range<U>(
+        &mut self,
+        avma_range: Range<u64>,
+        unwind_info: CodeUnwindInfo,
+        name: Option<&str>,
+        unwinder: &mut U,
+    ) where
+        U: Unwinder<Module = Module<ModuleData>>,
+    {
+        self.unregister_code_range(avma_range.start, unwinder);
+
+        let name = name.unwrap_or(JIT_PATH);
+        let has_cfi = matches!(unwind_info, CodeUnwindInfo::EhFrame { .. });
+        if let CodeUnwindInfo::EhFrame { data, address } = unwind_info {
+            // The code is not relocated, so its addresses are the ones in the unwind info.
+            // framehop looks up relative addresses, so the base must be close to the code.
+            let section_info = ExplicitModuleSectionInfo {
+                base_svma: avma_range.start,
+                text_svma: Some(avma_range.clone()),
+                eh_frame_svma: Some(address..address + data.len() as u64),
+                eh_frame: Some(ModuleData::from(data)),
+                ..Default::default()
+            };
+            unwinder.add_module(Module::new(
+                name.to_owned(),
+                avma_range.clone(),
+                avma_range.start,
+                section_info,
+            ));
+        }
+        let index = self
+            .images
+            .partition_point(|registered| registered.avma_range.start < avma_range.start);
+        let code_range = avma_range.clone();
+        self.images.insert(
+            index,
+            RegisteredImage {
+                path: Path::new(name).into(),
+                bias: 0,
+                avma_range,
+                index: self.next_index,
+                // The code is in this process, so it can be read.
+                code_ranges: vec![code_range],
+                jit: true,
+                has_cfi,
+            },
+        );
+        self.next_index += 1;
+    }
+
+    /// Removes the code range registered at `start`. Returns whether there was one.
+    pub(crate) fn unregister_code_range<U>(&mut self, start: u64, unwinder: &mut U) -> bool
+    where
+        U: Unwinder<Module = Module<ModuleData>>,
+    {
+        let Some(position) = self
+            .images
+            .iter()
+            .position(|image| image.jit && image.avma_range.start == start)
+        else {
+            return false;
+        };
+        self.images.remove(position);
+        unwinder.remove_module(start);
+        true
+    }
+
+    /// Registers the images loaded since the last call with `unwinder`, and removes the ones
+    /// that have been unloaded (e.g. by `dlopen` / `dlclose`).
+    pub(crate) fn refresh<U>(&mut self, unwinder: &mut U)
+    where
+        U: Unwinder<Module = Module<ModuleData>>,
+    {
+        let loaded = loaded_images(self.from_memory);
+        evict_sections(&loaded);
+        self.refresh_from(loaded, unwinder);
+    }
+
+    /// Like [`refresh`](Self::refresh), for the images `loaded` into any process.
+    pub(crate) fn refresh_from<U>(&mut self, mut loaded: Vec<LoadedImage>, unwinder: &mut U)
+    where
+        U: Unwinder<Module = Module<ModuleData>>,
+    {
+        loaded.sort_unstable_by_key(|image| image.avma_range.start);
+
+        self.images.retain(|registered| {
+            let still_loaded = registered.jit
+                || loaded.iter().any(|image| {
+                    image.avma_range == registered.avma_range && *image.path == *registered.path
+                });
+            if !still_loaded {
+                unwinder.remove_module(registered.avma_range.start);
+            }
+            still_loaded
+        });
+
+        for image in loaded {
+            let index = self
+                .images
+                .partition_point(|registered| registered.avma_range.start < image.avma_range.start);
+            if self.images.get(index).is_some_and(|registered| {
+                registered.avma_range == image.avma_range && *registered.path == *image.path
+            }) {
+                continue;
+            }
+            // Images without a readable file (e.g. the vDSO) are still recorded, so that
+            // addresses inside them don't trigger another refresh.
+            let module = if self.from_memory {
+                memory_module(&image)
+            } else {
+                load_module(&image)
+            };
+            let has_cfi = module.is_some();
+            if let Some(module) = module {
+                unwinder.add_module(module);
+            }
+            self.images.insert(
+                index,
+                RegisteredImage {
+                    path: image.path.into(),
+                    bias: image.bias,
+                    avma_range: image.avma_range,
+                    index: self.next_index,
+                    code_ranges: image.code_ranges,
+                    jit: false,
+                    has_cfi,
+                },
+            );
+            self.next_index += 1;
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Unwinding stack snapshots of any supported architecture, e.g. ones taken on arm64 devices
+//! and analyzed on an x86_64 machine.
+
+use framehop::{
+    aarch64::{CacheAarch64, PtrAuthMask, UnwindRegsAarch64, UnwinderAarch64},
+    x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64},
+    FrameAddress, Unwinder,
+};
+
+#[cfg(target_os = "linux")]
+use super::{module::Modules, SnapshotModule};
+use super::{reader::StackReader, Frame, ModuleData, StackSnapshot, TerminationReason};
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+/// An architecture whose stacks can be unwound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// The architecture of this process.
+    #[cfg(target_arch = "x86_64")]
+    pub const HOST: Self = Self::X86_64;
+    /// The architecture of this process.
+    #[cfg(target_arch = "aarch64")]
+    pub const HOST: Self = Self::Aarch64;
+}
+
+/// The framehop unwinder of an architecture.
+enum ArchUnwinder {
+    X86_64 {
+        unwinder: UnwinderX86_64<ModuleData>,
+        cache: CacheX86_64,
+    },
+    Aarch64 {
+        unwinder: UnwinderAarch64<ModuleData>,
+        cache: CacheAarch64,
+        /// `None` until set, to be deduced from the modules of each snapshot.
+        ptr_auth_mask: Option<PtrAuthMask>,
+    },
+}
+
+/// The registers of a walk, for the architecture of the unwinder.
+enum ArchRegs {
+    X86_64(UnwindRegsX86_64),
+    Aarch64(UnwindRegsAarch64),
+}
+
+impl ArchRegs {
+    fn sp(&self) -> u64 {
+        match self {
+            Self::X86_64(regs) => regs.sp(),
+            Self::Aarch64(regs) => regs.sp(),
+        }
+    }
+
+    fn fp(&self) -> u64 {
+        match self {
+            Self::X86_64(regs) => regs.bp(),
+            Self::Aarch64(regs) => regs.fp(),
+        }
+    }
+}
+
+/// Unwinds [`StackSnapshot`]s of an architecture chosen at runtime, which need not be the one
+/// of this process.
+///
+/// Unlike [`StackUnwinder::unwind_snapshot`](super::StackUnwinder::unwind_snapshot), the walk
+/// never uses the images of this process: the unwind information is read from the files of
+/// the modules of the snapshot. Change their paths first if the files are elsewhere on this
+/// machine, e.g. in a copy of the root filesystem of the device.
+pub struct SnapshotUnwinder {
+    arch: Arch,
+    unwinder: ArchUnwinder,
+    #[cfg(target_os = "linux")]
+    modules: Modules,
+    max_frames: Option<usize>,
+}
+
+impl SnapshotUnwinder {
+    pub fn new(arch: Arch) -> Self {
+        let unwinder = match arch {
+            Arch::X86_64 => ArchUnwinder::X86_64 {
+                unwinder: UnwinderX86_64::new(),
+                cache: CacheX86_64::new(),
+            },
+            Arch::Aarch64 => ArchUnwinder::Aarch64 {
+                unwinder: UnwinderAarch64::new(),
+                cache: CacheAarch64::new(),
+                ptr_auth_mask: None,
+            },
+        };
+        Self {
+            arch,
+            unwinder,
+            #[cfg(target_os = "linux")]
+            modules: Modules::new(),
+            max_frames: None,
+        }
+    }
+
+    /// Stops a walk after `max_frames` frames.
+    pub fn with_max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = Some(max_frames);
+        self
+    }
+
+    /// Strips pointer-authentication bits from the return addresses of aarch64 snapshots with
+    /// `ptr_auth_mask`. By default, every bit above the end of the highest module of the
+    /// snapshot is stripped, or none if it has no modules.
+    pub fn with_ptr_auth_mask(mut self, mask: PtrAuthMask) -> Self {
+        if let ArchUnwinder::Aarch64 { ptr_auth_mask, .. } = &mut self.unwinder {
+            *ptr_auth_mask = Some(mask);
+        }
+        self
+    }
+
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// Unwinds `snapshot`, which must have been taken on the architecture of this unwinder.
+    /// The walk ends where the copy of the stack ends.
+    pub fn unwind_snapshot<'a>(&'a mut self, snapshot: &'a StackSnapshot) -> SnapshotIterator<'a> {
+        #[cfg(target_os = "linux")]
+        if !snapshot.modules.is_empty() {
+            // The vDSO of this process only stands in for the one of the snapshot if they
+            // are built for the same architecture.
+            let images = snapshot
+                .modules
+                .iter()
+                .filter(|module| {
+                    self.arch == Arch::HOST || *module.path != *Path::new(crate::vdso::PATH)
+                })
+                .map(Into::into)
+                .collect();
+            match &mut self.unwinder {
+                ArchUnwinder::X86_64 { unwinder, .. } => {
+                    self.modules.refresh_from(images, unwinder)
+                }
+                ArchUnwinder::Aarch64 { unwinder, .. } => {
+                    self.modules.refresh_from(images, unwinder)
+                }
+            }
+        }
+
+        let regs = match &self.unwinder {
+            ArchUnwinder::X86_64 { .. } => {
+                ArchRegs::X86_64(UnwindRegsX86_64::new(snapshot.pc, snapshot.sp, snapshot.fp))
+            }
+            ArchUnwinder::Aarch64 { ptr_auth_mask, .. } => {
+                let ptr_auth_mask = ptr_auth_mask.unwrap_or_else(|| {
+                    snapshot
+                        .modules
+                        .iter()
+                        .map(|module| module.avma_range.end)
+                        .max()
+                        .map_or_else(PtrAuthMask::new_no_strip, |end| {
+                            PtrAuthMask::from_max_known_address(end)
+                        })
+                });
+                ArchRegs::Aarch64(UnwindRegsAarch64::new_with_ptr_auth_mask(
+                    ptr_auth_mask,
+                    snapshot.lr,
+                    snapshot.sp,
+                    snapshot.fp,
+                ))
+            }
+        };
+        let remaining_frames = self.max_frames;
+        SnapshotIterator {
+            unwinder: self,
+            reader: StackReader::Copied {
+                base: snapshot.stack_base,
+                bytes: &snapshot.stack,
+            },
+            regs,
+            state: SnapshotIteratorState::Initial(snapshot.pc),
+            remaining_frames,
+        }
+    }
+
+    /// Returns the module of the last unwound snapshot with the given
+    /// [index](Frame::module_index).
+    #[cfg(target_os = "linux")]
+    pub fn module(&self, index: usize) -> Option<SnapshotModule> {
+        self.modules.get(index).map(Into::into)
+    }
+
+    /// Returns the [index](Frame::module_index) of the module containing `address`.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn module_index(&self, address: u64) -> Option<usize> {
+        #[cfg(target_os = "linux")]
+        return self.modules.find(address).map(|image| image.index);
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+
+    /// Explains why unwinding the frame at `address` failed with `error`.
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn termination_reason(
+        &self,
+        address: FrameAddress,
+        error: framehop::Error,
+    ) -> TerminationReason {
+        #[cfg(target_os = "linux")]
+        return self.modules.termination_reason(address, error);
+        #[cfg(not(target_os = "linux"))]
+        error.into()
+    }
+}
+
+enum SnapshotIteratorState {
+    Initial(u64),
+    Unwinding(FrameAddress),
+    Done(TerminationReason),
+}
+
+/// The frames of a snapshot, see [`SnapshotUnwinder::unwind_snapshot`].
+pub struct SnapshotIterator<'a> {
+    unwinder: &'a mut SnapshotUnwinder,
+    reader: StackReader<'a>,
+    regs: ArchRegs,
+    state: SnapshotIteratorState,
+    /// Number of frames still to be yielded, if limited.
+    remaining_frames: Option<usize>,
+}
+
+impl SnapshotIterator<'_> {
+    /// Returns why the walk ended, or `None` if it has not ended yet.
+    pub fn termination_reason(&self) -> Option<&TerminationReason> {
+        match &self.state {
+            SnapshotIteratorState::Done(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Unwinds the frame at `address`, returning the address of its caller.
+    fn unwind_frame(&mut self, address: FrameAddress) -> Result<FrameAddress, TerminationReason> {
+        let reader = &self.reader;
+        let mut read_stack = |address| reader.read_u64(address);
+        let result = match (&mut self.unwinder.unwinder, &mut self.regs) {
+            (ArchUnwinder::X86_64 { unwinder, cache }, ArchRegs::X86_64(regs)) => {
+                unwinder.unwind_frame(address, regs, cache, &mut read_stack)
+            }
+            (
+                ArchUnwinder::Aarch64 {
+                    unwinder, cache, ..
+                },
+                ArchRegs::Aarch64(regs),
+            ) => {
+                // framehop doesn't strip the return addresses it gets from DWARF CFI.
+                let ptr_auth_mask = regs.lr_mask();
+                unwinder
+                    .unwind_frame(address, regs, cache, &mut read_stack)
+                    .map(|address| address.map(|address| ptr_auth_mask.strip_ptr_auth(address)))
+            }
+            _ => unreachable!("the registers are created for the architecture of the unwinder"),
+        };
+        let result = match result {
+            Ok(Some(return_address)) => FrameAddress::from_return_address(return_address)
+                .ok_or(framehop::Error::ReturnAddressIsNull),
+            Ok(None) => return Err(TerminationReason::EndOfStack),
+            Err(error) => Err(error),
+        };
+        result.map_err(|error| self.unwinder.termination_reason(address, error))
+    }
+}
+
+impl Iterator for SnapshotIterator<'_> {
+    type Item = Frame;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining_frames == Some(0) {
+            if self.termination_reason().is_none() {
+                self.state = SnapshotIteratorState::Done(TerminationReason::DepthLimit);
+            }
+            return None;
+        }
+        let address = match &self.state {
+            SnapshotIteratorState::Initial(pc) => FrameAddress::InstructionPointer(*pc),
+            SnapshotIteratorState::Unwinding(address) => {
+                let address = *address;
+                match self.unwind_frame(address) {
+                    Ok(address) => address,
+                    Err(reason) => {
+                        self.state = SnapshotIteratorState::Done(reason);
+                        return None;
+                    }
+                }
+            }
+            SnapshotIteratorState::Done(_) => return None,
+        };
+        self.state = SnapshotIteratorState::Unwinding(address);
+        if let Some(remaining_frames) = &mut self.remaining_frames {
+            *remaining_frames -= 1;
+        }
+
+        let module_index = self.unwinder.module_index(address.address_for_lookup());
+        Some(Frame::new(
+            address,
+            self.regs.sp(),
+            self.regs.fp(),
+            module_index,
+        ))
+    }
+}
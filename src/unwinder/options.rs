@@ -0,0 +1,16 @@
+/// Options shared by the unwinders of every architecture, set through the builders.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct UnwindOptions {
+    /// Maximum number of frames yielded by a walk. `None` means unlimited.
+    pub(crate) max_frames: Option<usize>,
+    /// Number of frames dropped at the start of a walk.
+    pub(crate) skip_frames: usize,
+    /// Whether to drop the frame of `StackUnwinder::unwind` itself.
+    pub(crate) skip_internal_frame: bool,
+    /// Whether to scan the stack for a return address when unwinding a frame fails.
+    #[cfg(target_os = "linux")]
+    pub(crate) stack_scanning: bool,
+    /// Whether to read the unwind information of the loaded images from memory only.
+    #[cfg(target_os = "linux")]
+    pub(crate) unwind_info_from_memory: bool,
+}
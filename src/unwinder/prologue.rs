@@ -0,0 +1,108 @@
+//! Unwinding the first frame of a walk in code without CFI, while a function sets up or tears
+//! down its frame.
+//!
+//! A profiler may interrupt a thread at any instruction. Before the prologue has saved the
+//! frame pointer of the caller and pointed it at the new frame, or after the epilogue has
+//! restored it, the frame pointer still describes the caller, so unwinding with it skips the
+//! caller. The instructions around the program counter tell where the return address is
+//! instead.
+
+/// Number of bytes of code read before the program counter.
+pub(crate) const CODE_BEFORE: u64 = 8;
+/// Number of bytes of code read from the program counter.
+pub(crate) const CODE_AFTER: u64 = 8;
+
+/// The state of a frame whose frame pointer doesn't point to its frame record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameSetup {
+    /// Nothing of the frame is on the stack: the return address is at the stack pointer on
+    /// x86_64, or in the link register on aarch64, and the frame pointer is the caller's.
+    NotStarted,
+    /// The frame record, the frame pointer of the caller followed by the return address, is
+    /// at the stack pointer, but the frame pointer is not set to it yet. The caller's stack
+    /// pointer is `frame_size` bytes above.
+    RecordSaved { frame_size: u64 },
+}
+
+/// The state of the frame executing the instruction at `pc`, the start of
+/// `code[CODE_BEFORE..]`, if it is in a prologue or an epilogue.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn frame_setup(
+    pc: u64,
+    code: [u8; (CODE_BEFORE + CODE_AFTER) as usize],
+) -> Option<FrameSetup> {
+    let (before, at) = code.split_at(CODE_BEFORE as usize);
+    // `push rbp`, possibly after `endbr64`; `ret` or `rep ret`.
+    if at.starts_with(&[0x55])
+        || at.starts_with(&[0xf3, 0x0f, 0x1e, 0xfa])
+        || at.starts_with(&[0xc3])
+        || at.starts_with(&[0xf3, 0xc3])
+    {
+        return Some(FrameSetup::NotStarted);
+    }
+    // `mov rbp, rsp`, in either encoding, right after `push rbp`.
+    if follows_function_start(pc, before)
+        && (at.starts_with(&[0x48, 0x89, 0xe5]) || at.starts_with(&[0x48, 0x8b, 0xec]))
+    {
+        return Some(FrameSetup::RecordSaved { frame_size: 16 });
+    }
+    None
+}
+
+/// Whether the instruction before `pc` is a `push rbp` starting a function.
+///
+/// Its byte 0x55 may also end another instruction, e.g. as an immediate, so it is only taken
+/// for `push rbp` where compilers start functions: at a 16-byte boundary, possibly after an
+/// `endbr64` there.
+#[cfg(target_arch = "x86_64")]
+fn follows_function_start(pc: u64, before: &[u8]) -> bool {
+    const ENDBR64: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+    let Some((&last, before_push)) = before.split_last() else {
+        return false;
+    };
+    let push = pc.wrapping_sub(1);
+    last == 0x55 && (push % 16 == 0 || (push % 16 == 4 && before_push.ends_with(&ENDBR64)))
+}
+
+/// The state of the frame executing the instruction at `pc`, the start of
+/// `code[CODE_BEFORE..]`, if it is in a prologue or an epilogue.
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn frame_setup(
+    _pc: u64,
+    code: [u8; (CODE_BEFORE + CODE_AFTER) as usize],
+) -> Option<FrameSetup> {
+    const PACIASP: u32 = 0xd503_233f;
+    const PACIBSP: u32 = 0xd503_237f;
+    const BTI_C: u32 = 0xd503_245f;
+    const RET: u32 = 0xd65f_03c0;
+    const RETAA: u32 = 0xd65f_0bff;
+    const RETAB: u32 = 0xd65f_0fff;
+    const MOV_X29_SP: u32 = 0x9100_03fd;
+
+    let instruction = |offset: usize| {
+        u32::from_le_bytes([
+            code[offset],
+            code[offset + 1],
+            code[offset + 2],
+            code[offset + 3],
+        ])
+    };
+    // `stp x29, x30, [sp, #-frame_size]!`.
+    let is_stp_frame_record = |instruction: u32| instruction & 0xffc0_7fff == 0xa980_7bfd;
+    let (previous, current) = (instruction(4), instruction(8));
+
+    if is_stp_frame_record(current)
+        || matches!(current, PACIASP | PACIBSP | BTI_C | RET | RETAA | RETAB)
+    {
+        return Some(FrameSetup::NotStarted);
+    }
+    if current == MOV_X29_SP && is_stp_frame_record(previous) {
+        // The offset is a signed 7-bit immediate, scaled by 8.
+        let offset = ((previous >> 15) & 0x7f) as i64;
+        let offset = (offset << 57) >> 57;
+        return Some(FrameSetup::RecordSaved {
+            frame_size: (-offset * 8) as u64,
+        });
+    }
+    None
+}
@@ -0,0 +1,278 @@
+//! Reading stack memory during a walk without trusting the addresses being read.
+
+/// Address range `[start, end)` of a stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackBounds {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl StackBounds {
+    pub fn new(start: u64, end: u64) -> Self {
+        Self { start, end }
+    }
+
+    /// Whether the `size` bytes at `address` are inside the stack.
+    pub fn contains(&self, address: u64, size: u64) -> bool {
+        address >= self.start && address.checked_add(size).is_some_and(|end| end <= self.end)
+    }
+
+    /// Returns the bounds of the calling thread's stack.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn current_thread() -> Option<Self> {
+        imp::current_thread_stack()
+    }
+
+    /// Returns the bounds of the calling thread's alternate signal stack, if one is installed.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn signal_stack() -> Option<Self> {
+        let mut stack: libc::stack_t = unsafe { std::mem::zeroed() };
+        if unsafe { libc::sigaltstack(std::ptr::null(), &mut stack) } != 0
+            || stack.ss_flags & libc::SS_DISABLE != 0
+        {
+            return None;
+        }
+        let start = stack.ss_sp as u64;
+        Some(Self::new(start, start + stack.ss_size as u64))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::StackBounds;
+
+    pub(super) fn current_thread_stack() -> Option<StackBounds> {
+        unsafe {
+            let mut attr: libc::pthread_attr_t = std::mem::zeroed();
+            if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+                return main_thread_stack();
+            }
+            let mut addr = std::ptr::null_mut();
+            let mut size = 0;
+            let result = libc::pthread_attr_getstack(&attr, &mut addr, &mut size);
+            libc::pthread_attr_destroy(&mut attr);
+            if result != 0 {
+                return None;
+            }
+            let start = addr as u64;
+            Some(StackBounds::new(start, start + size as u64))
+        }
+    }
+
+    /// The size assumed for the stack of the main thread when it has no size limit, glibc's
+    /// default limit. Unlike the stack pointer, it doesn't depend on how deep the stack is
+    /// when the bounds are looked up, which are cached for later walks.
+    #[cfg(target_env = "gnu")]
+    const UNLIMITED_MAIN_THREAD_STACK_SIZE: u64 = 8 << 20;
+
+    /// Returns the bounds of the stack of the main thread without `/proc`.
+    ///
+    /// glibc reads `/proc/self/maps` to find the stack of the main thread, which fails e.g. in
+    /// a sandbox without `/proc`. The stack then ends after the top of the stack when the
+    /// process started, and starts where the stack size limit lets it grow to. The kernel maps
+    /// the part not yet used on access, so it can be read. Without a limit, it is assumed to
+    /// be at most [`UNLIMITED_MAIN_THREAD_STACK_SIZE`], and walks of a deeper stack stop early.
+    #[cfg(target_env = "gnu")]
+    fn main_thread_stack() -> Option<StackBounds> {
+        extern "C" {
+            static __libc_stack_end: *const libc::c_void;
+        }
+
+        if crate::unwinder::gettid() != unsafe { libc::getpid() } {
+            return None;
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let top = unsafe { __libc_stack_end } as u64;
+        if top == 0 {
+            return None;
+        }
+        let end = (top + page_size - 1) & !(page_size - 1);
+        let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+        let size = if unsafe { libc::getrlimit(libc::RLIMIT_STACK, &mut limit) } == 0
+            && limit.rlim_cur != libc::RLIM_INFINITY
+        {
+            limit.rlim_cur as u64
+        } else {
+            UNLIMITED_MAIN_THREAD_STACK_SIZE
+        };
+        let start = end.saturating_sub(size);
+        Some(StackBounds::new(start, end))
+    }
+
+    #[cfg(not(target_env = "gnu"))]
+    fn main_thread_stack() -> Option<StackBounds> {
+        None
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use super::StackBounds;
+
+    pub(super) fn current_thread_stack() -> Option<StackBounds> {
+        unsafe {
+            let thread = libc::pthread_self();
+            // `pthread_get_stackaddr_np` returns the highest address of the stack.
+            let end = libc::pthread_get_stackaddr_np(thread) as u64;
+            let size = libc::pthread_get_stacksize_np(thread) as u64;
+            Some(StackBounds::new(end - size, end))
+        }
+    }
+}
+
+/// Reads the stack during a walk.
+pub(crate) enum StackReader<'a> {
+    /// Reads the live stack, refusing misaligned addresses and addresses outside of `stack`
+    /// and `signal_stack`.
+    ///
+    /// A corrupted frame pointer or return address then terminates the walk instead of
+    /// crashing the process.
+    Live {
+        stack: Option<StackBounds>,
+        signal_stack: Option<StackBounds>,
+    },
+    /// Reads a copy of the stack whose first byte was at `base`.
+    Copied { base: u64, bytes: &'a [u8] },
+}
+
+impl<'a> StackReader<'a> {
+    /// A reader for `stack` only, e.g. the stack of another thread.
+    pub(crate) fn new(stack: Option<StackBounds>) -> Self {
+        Self::Live {
+            stack,
+            signal_stack: None,
+        }
+    }
+
+    pub(crate) fn read_u64(&self, address: u64) -> Result<u64, ()> {
+        match self {
+            Self::Live {
+                stack,
+                signal_stack,
+            } => {
+                if address % 8 != 0 {
+                    return Err(());
+                }
+                let in_bounds = |bounds: &Option<StackBounds>| {
+                    bounds.is_some_and(|bounds| bounds.contains(address, 8))
+                };
+                if !in_bounds(stack) && !in_bounds(signal_stack) {
+                    return Err(());
+                }
+                Ok(unsafe { std::ptr::read(address as *const u64) })
+            }
+            Self::Copied { base, bytes } => {
+                let offset =
+                    usize::try_from(address.checked_sub(*base).ok_or(())?).map_err(|_| ())?;
+                let bytes = bytes.get(offset..offset.checked_add(8).ok_or(())?);
+                Ok(u64::from_ne_bytes(bytes.ok_or(())?.try_into().unwrap()))
+            }
+        }
+    }
+
+    /// Copies up to `max_len` bytes of the stack starting at `sp`.
+    pub(crate) fn copy_stack(&self, sp: u64, max_len: usize) -> Vec<u8> {
+        let end = match self {
+            Self::Live {
+                stack,
+                signal_stack,
+            } => [stack, signal_stack]
+                .into_iter()
+                .flatten()
+                .find(|bounds| bounds.contains(sp, 1))
+                .map(|bounds| bounds.end),
+            Self::Copied { base, bytes } => Some(base + bytes.len() as u64),
+        };
+        let Some(end) = end.filter(|&end| sp < end) else {
+            return Vec::new();
+        };
+        let len = (end - sp).min(max_len as u64) as usize;
+        match self {
+            Self::Live { .. } => {
+                unsafe { std::slice::from_raw_parts(sp as *const u8, len) }.to_vec()
+            }
+            Self::Copied { base, bytes } => {
+                let offset = (sp - base) as usize;
+                bytes[offset..offset + len].to_vec()
+            }
+        }
+    }
+}
+
+/// Remembers the stack bounds of the thread an unwinder is used on.
+///
+/// Looking up the bounds is not async-signal-safe, so it is only done when the unwinder is
+/// used on a different thread than the last time.
+#[derive(Default)]
+pub(crate) struct CurrentThreadStack {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    cached: Option<(libc::pthread_t, Option<StackBounds>)>,
+}
+
+impl CurrentThreadStack {
+    pub(crate) fn new() -> Self {
+        let mut stack = Self::default();
+        stack.reader();
+        stack
+    }
+
+    /// Returns a reader for the stacks of the calling thread.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub(crate) fn reader(&mut self) -> StackReader<'static> {
+        let thread = unsafe { libc::pthread_self() };
+        let stack = match self.cached {
+            Some((cached_thread, stack))
+                if unsafe { libc::pthread_equal(cached_thread, thread) } != 0 =>
+            {
+                stack
+            }
+            _ => {
+                let stack = StackBounds::current_thread();
+                self.cached = Some((thread, stack));
+                stack
+            }
+        };
+        StackReader::Live {
+            stack,
+            signal_stack: StackBounds::signal_stack(),
+        }
+    }
+
+    /// Returns a reader for the stacks of the calling thread.
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub(crate) fn reader(&mut self) -> StackReader<'static> {
+        StackReader::Live {
+            stack: None,
+            signal_stack: None,
+        }
+    }
+
+    /// Like [`reader`](Self::reader), but async-signal-safe: the bounds of the stack are only
+    /// known if they are cached for the calling thread, otherwise only the alternate signal
+    /// stack can be read.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub(crate) fn cached_reader(&self) -> StackReader<'static> {
+        let thread = unsafe { libc::pthread_self() };
+        let stack = match self.cached {
+            Some((cached_thread, stack))
+                if unsafe { libc::pthread_equal(cached_thread, thread) } != 0 =>
+            {
+                stack
+            }
+            _ => None,
+        };
+        StackReader::Live {
+            stack,
+            signal_stack: StackBounds::signal_stack(),
+        }
+    }
+
+    /// Like [`reader`](Self::reader), but async-signal-safe.
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    pub(crate) fn cached_reader(&self) -> StackReader<'static> {
+        StackReader::Live {
+            stack: None,
+            signal_stack: None,
+        }
+    }
+}
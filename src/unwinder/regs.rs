@@ -0,0 +1,79 @@
+//! The registers a walk starts from.
+
+/// The registers needed to start a walk.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Regs {
+    pub(crate) pc: u64,
+    pub(crate) sp: u64,
+    pub(crate) fp: u64,
+    /// The link register, or 0 on architectures without one.
+    pub(crate) lr: u64,
+}
+
+impl Regs {
+    /// Reads the registers saved in the `ucontext` passed to a `SA_SIGINFO` signal handler.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub(crate) fn from_ucontext(ucontext: &libc::ucontext_t) -> Self {
+        let gregs = &ucontext.uc_mcontext.gregs;
+        Self {
+            pc: gregs[libc::REG_RIP as usize] as u64,
+            sp: gregs[libc::REG_RSP as usize] as u64,
+            fp: gregs[libc::REG_RBP as usize] as u64,
+            lr: 0,
+        }
+    }
+
+    /// Reads the registers saved in the `ucontext` passed to a `SA_SIGINFO` signal handler.
+    #[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+    pub(crate) fn from_ucontext(ucontext: &libc::ucontext_t) -> Self {
+        let mcontext = &ucontext.uc_mcontext;
+        Self {
+            pc: mcontext.pc,
+            sp: mcontext.sp,
+            fp: mcontext.regs[29],
+            lr: mcontext.regs[30],
+        }
+    }
+}
+
+/// Reads the registers of the calling function. This is a macro so that the registers are
+/// guaranteed to belong to the frame that keeps walking the stack.
+#[cfg(target_arch = "x86_64")]
+macro_rules! current_regs {
+    () => {{
+        let mut pc = 0;
+        let mut sp = 0;
+        let mut fp = 0;
+        unsafe {
+            ::std::arch::asm!("lea {}, [rip]", out(reg) pc);
+            ::std::arch::asm!("mov {}, rsp", out(reg) sp);
+            ::std::arch::asm!("mov {}, rbp", out(reg) fp);
+        }
+        $crate::unwinder::regs::Regs { pc, sp, fp, lr: 0 }
+    }};
+}
+
+/// Reads the registers of the calling function. This is a macro so that the registers are
+/// guaranteed to belong to the frame that keeps walking the stack.
+#[cfg(target_arch = "aarch64")]
+macro_rules! current_regs {
+    () => {{
+        let mut pc = 0;
+        let mut lr = 0;
+        let mut sp = 0;
+        let mut fp = 0;
+        unsafe {
+            // Get current PC (program counter)
+            ::std::arch::asm!("adr {}, .", out(reg) pc);
+            // Get LR (link register - x30)
+            ::std::arch::asm!("mov {}, x30", out(reg) lr);
+            // Get SP (stack pointer)
+            ::std::arch::asm!("mov {}, sp", out(reg) sp);
+            // Get FP (frame pointer - x29)
+            ::std::arch::asm!("mov {}, x29", out(reg) fp);
+        }
+        $crate::unwinder::regs::Regs { pc, sp, fp, lr }
+    }};
+}
+
+pub(crate) use current_regs;
@@ -0,0 +1,347 @@
+//! Unwinding the threads of another process, like `pstack` or `eu-stack`.
+//!
+//! The threads are stopped with ptrace, and the stack memory is read with
+//! `process_vm_readv`.
+
+use super::{
+    module::Modules, regs::Regs, signal, Frame, FrameKind, ModuleData, SnapshotModule,
+    TerminationReason,
+};
+use framehop::{FrameAddress, Unwinder};
+use std::{ffi::c_void, fmt, io};
+
+#[cfg(target_arch = "aarch64")]
+use framehop::aarch64::{
+    CacheAarch64 as Cache, UnwindRegsAarch64 as UnwindRegs, UnwinderAarch64 as ArchUnwinder,
+};
+#[cfg(target_arch = "x86_64")]
+use framehop::x86_64::{
+    CacheX86_64 as Cache, UnwindRegsX86_64 as UnwindRegs, UnwinderX86_64 as ArchUnwinder,
+};
+
+/// Default limit of the frames of each thread.
+const MAX_FRAMES: usize = 512;
+
+/// The stack of one thread of another process.
+#[derive(Debug)]
+pub struct RemoteThreadStack {
+    pub tid: libc::pid_t,
+    /// The name of the thread, from `/proc/<pid>/task/<tid>/comm`.
+    pub name: String,
+    pub frames: Vec<Frame>,
+    /// Why the walk ended.
+    pub termination_reason: TerminationReason,
+}
+
+impl fmt::Display for RemoteThreadStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\"{}\" tid={}", self.name, self.tid)?;
+        for (index, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "  #{index:<3} 0x{:016x}", frame.address())?;
+        }
+        if !self.termination_reason.is_complete() {
+            writeln!(f, "  <{}>", self.termination_reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Unwinds the threads of another process.
+///
+/// Attaching requires the permission to ptrace the process, e.g. being its parent or having
+/// `CAP_SYS_PTRACE`, depending on `/proc/sys/kernel/yama/ptrace_scope`.
+pub struct RemoteUnwinder {
+    pid: libc::pid_t,
+    cache: Cache,
+    unwinder: ArchUnwinder<ModuleData>,
+    modules: Modules,
+    max_frames: usize,
+    #[cfg(target_arch = "aarch64")]
+    ptr_auth_mask: framehop::aarch64::PtrAuthMask,
+}
+
+impl RemoteUnwinder {
+    /// Prepares to unwind the process `pid`, registering the images it has mapped.
+    pub fn new(pid: libc::pid_t) -> io::Result<Self> {
+        let mut remote = Self {
+            pid,
+            cache: Cache::new(),
+            unwinder: ArchUnwinder::new(),
+            modules: Modules::new(),
+            max_frames: MAX_FRAMES,
+            #[cfg(target_arch = "aarch64")]
+            ptr_auth_mask: super::aarch64::default_ptr_auth_mask(),
+        };
+        remote.refresh_modules()?;
+        Ok(remote)
+    }
+
+    /// Stops each walk after `max_frames` frames. The default is 512.
+    pub fn with_max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = max_frames;
+        self
+    }
+
+    /// Strips pointer-authentication bits from return addresses with `ptr_auth_mask`. By
+    /// default, the mask is the one applied by the `xpaclri` instruction on this CPU.
+    #[cfg(target_arch = "aarch64")]
+    pub fn with_ptr_auth_mask(mut self, ptr_auth_mask: framehop::aarch64::PtrAuthMask) -> Self {
+        self.ptr_auth_mask = ptr_auth_mask;
+        self
+    }
+
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// Re-reads the images mapped into the process, e.g. after it has loaded a library.
+    pub fn refresh_modules(&mut self) -> io::Result<()> {
+        let images = super::module::remote_images(self.pid)?;
+        self.modules.refresh_from(images, &mut self.unwinder);
+        Ok(())
+    }
+
+    /// Returns the module with the given [index](Frame::module_index), or `None` if it has
+    /// been unloaded since.
+    pub fn module(&self, index: usize) -> Option<SnapshotModule> {
+        self.modules.get(index).map(SnapshotModule::from)
+    }
+
+    /// Stops every thread of the process, unwinds their stacks, and resumes them.
+    ///
+    /// Threads are ordered by thread ID. Threads that exit before they are stopped are left
+    /// out.
+    pub fn unwind_all_threads(&mut self) -> io::Result<Vec<RemoteThreadStack>> {
+        let mut tids = std::fs::read_dir(format!("/proc/{}/task", self.pid))?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect::<Vec<libc::pid_t>>();
+        tids.sort_unstable();
+
+        // Stop every thread first, so that the stacks are consistent with each other.
+        let mut attached = Vec::with_capacity(tids.len());
+        for tid in tids {
+            match AttachedThread::attach(tid) {
+                Ok(thread) => attached.push(thread),
+                Err(error) if error.raw_os_error() == Some(libc::ESRCH) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        let mut stacks = Vec::with_capacity(attached.len());
+        for thread in &attached {
+            let regs = thread.regs()?;
+            stacks.push(self.unwind_regs(thread.tid, regs));
+        }
+        Ok(stacks)
+    }
+
+    fn unwind_regs(&mut self, tid: libc::pid_t, regs: Regs) -> RemoteThreadStack {
+        #[cfg(target_arch = "x86_64")]
+        let mut unwind_regs = UnwindRegs::new(regs.pc, regs.sp, regs.fp);
+        #[cfg(target_arch = "aarch64")]
+        let mut unwind_regs =
+            UnwindRegs::new_with_ptr_auth_mask(self.ptr_auth_mask, regs.lr, regs.sp, regs.fp);
+
+        let memory = RemoteMemory { tid };
+        let mut read_stack = |address| memory.read_u64(address);
+        let mut frames = Vec::new();
+        let mut address = FrameAddress::InstructionPointer(regs.pc);
+        let mut interrupted = false;
+        let termination_reason = loop {
+            #[cfg(target_arch = "x86_64")]
+            let fp = unwind_regs.bp();
+            #[cfg(target_arch = "aarch64")]
+            let fp = unwind_regs.fp();
+            let module_index = self
+                .modules
+                .find(address.address_for_lookup())
+                .map(|image| image.index);
+            let frame = Frame::new(address, unwind_regs.sp(), fp, module_index);
+            if std::mem::take(&mut interrupted) {
+                frames.push(frame.with_kind(FrameKind::InterruptedBySignal));
+            } else {
+                frames.push(frame);
+            }
+            if frames.len() >= self.max_frames {
+                break TerminationReason::DepthLimit;
+            }
+
+            // The registers of code interrupted by a signal are saved in the signal frame.
+            if signal::is_trampoline(address.address(), |address| memory.read_u64(address)) {
+                let regs = match signal::saved_regs(unwind_regs.sp(), &mut read_stack) {
+                    Ok(regs) if regs.pc != 0 => regs,
+                    Ok(_) => break framehop::Error::ReturnAddressIsNull.into(),
+                    Err(address) => break TerminationReason::MemoryReadFailed { address },
+                };
+                #[cfg(target_arch = "x86_64")]
+                {
+                    unwind_regs = UnwindRegs::new(regs.pc, regs.sp, regs.fp);
+                }
+                #[cfg(target_arch = "aarch64")]
+                {
+                    unwind_regs = UnwindRegs::new_with_ptr_auth_mask(
+                        self.ptr_auth_mask,
+                        regs.lr,
+                        regs.sp,
+                        regs.fp,
+                    );
+                }
+                address = FrameAddress::InstructionPointer(regs.pc);
+                interrupted = true;
+                continue;
+            }
+
+            let result = self.unwinder.unwind_frame(
+                address,
+                &mut unwind_regs,
+                &mut self.cache,
+                &mut read_stack,
+            );
+            match result {
+                Ok(Some(return_address)) => {
+                    #[cfg(target_arch = "aarch64")]
+                    let return_address = self.ptr_auth_mask.strip_ptr_auth(return_address);
+                    match FrameAddress::from_return_address(return_address) {
+                        Some(return_address) => address = return_address,
+                        None => break framehop::Error::ReturnAddressIsNull.into(),
+                    }
+                }
+                Ok(None) => break TerminationReason::EndOfStack,
+                Err(error) => break self.modules.termination_reason(address, error),
+            }
+        };
+
+        RemoteThreadStack {
+            tid,
+            name: std::fs::read_to_string(format!("/proc/{}/task/{tid}/comm", self.pid))
+                .map(|name| name.trim_end().to_owned())
+                .unwrap_or_default(),
+            frames,
+            termination_reason,
+        }
+    }
+}
+
+/// A thread stopped with ptrace, which is resumed when this is dropped.
+struct AttachedThread {
+    tid: libc::pid_t,
+    /// The signal the thread was about to receive when it stopped, delivered on detach.
+    pending_signal: libc::c_int,
+}
+
+impl AttachedThread {
+    /// Stops the thread with `PTRACE_SEIZE` and `PTRACE_INTERRUPT`, which, unlike
+    /// `PTRACE_ATTACH`, queue no `SIGSTOP` that would stop the process after the detach.
+    fn attach(tid: libc::pid_t) -> io::Result<Self> {
+        ptrace(libc::PTRACE_SEIZE, tid, 0, 0)?;
+        let mut thread = Self {
+            tid,
+            pending_signal: 0,
+        };
+        ptrace(libc::PTRACE_INTERRUPT, tid, 0, 0)?;
+        loop {
+            let mut status = 0;
+            if unsafe { libc::waitpid(tid, &mut status, libc::__WALL) } < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(error);
+            }
+            if libc::WIFSTOPPED(status) {
+                // The interrupt, or a group-stop, is reported as `PTRACE_EVENT_STOP`. Any other
+                // stop is the delivery of a signal, which stops the thread just as well but
+                // must not be lost.
+                if status >> 16 != libc::PTRACE_EVENT_STOP {
+                    thread.pending_signal = libc::WSTOPSIG(status);
+                }
+                return Ok(thread);
+            }
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                return Err(io::Error::from_raw_os_error(libc::ESRCH));
+            }
+        }
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn regs(&self) -> io::Result<Regs> {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        ptrace(
+            libc::PTRACE_GETREGS,
+            self.tid,
+            0,
+            &mut regs as *mut _ as usize,
+        )?;
+        Ok(Regs {
+            pc: regs.rip,
+            sp: regs.rsp,
+            fp: regs.rbp,
+            lr: 0,
+        })
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn regs(&self) -> io::Result<Regs> {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        let mut iovec = libc::iovec {
+            iov_base: &mut regs as *mut _ as *mut c_void,
+            iov_len: std::mem::size_of::<libc::user_regs_struct>(),
+        };
+        ptrace(
+            libc::PTRACE_GETREGSET,
+            self.tid,
+            libc::NT_PRSTATUS as usize,
+            &mut iovec as *mut _ as usize,
+        )?;
+        Ok(Regs {
+            pc: regs.pc,
+            sp: regs.sp,
+            fp: regs.regs[29],
+            lr: regs.regs[30],
+        })
+    }
+}
+
+impl Drop for AttachedThread {
+    fn drop(&mut self) {
+        let _ = ptrace(
+            libc::PTRACE_DETACH,
+            self.tid,
+            0,
+            self.pending_signal as usize,
+        );
+    }
+}
+
+fn ptrace(request: libc::c_uint, tid: libc::pid_t, address: usize, data: usize) -> io::Result<()> {
+    let result = unsafe { libc::ptrace(request, tid, address as *mut c_void, data as *mut c_void) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// The memory of another process, read with `process_vm_readv`. Reading an unmapped
+/// address fails instead of crashing, so no stack bounds are needed.
+struct RemoteMemory {
+    tid: libc::pid_t,
+}
+
+impl RemoteMemory {
+    fn read_u64(&self, address: u64) -> Result<u64, ()> {
+        let mut value = 0u64;
+        let local = libc::iovec {
+            iov_base: &mut value as *mut u64 as *mut c_void,
+            iov_len: 8,
+        };
+        let remote = libc::iovec {
+            iov_base: address as *mut c_void,
+            iov_len: 8,
+        };
+        let read = unsafe { libc::process_vm_readv(self.tid, &local, 1, &remote, 1, 0) };
+        if read != 8 {
+            return Err(());
+        }
+        Ok(value)
+    }
+}
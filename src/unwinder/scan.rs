@@ -0,0 +1,64 @@
+//! Stack scanning, the last resort when neither unwind information nor frame pointers lead to
+//! the caller of a frame.
+//!
+//! The words above the stack pointer are read one by one, and the first one that points
+//! right after a call instruction in a known module is taken as the return address. It may
+//! be a stale value left by an earlier call, so the frames found this way are guesses.
+
+/// Number of stack words read while looking for the return address of a frame.
+pub(crate) const SCAN_WORDS: u64 = 256;
+
+/// Number of bytes of code read before a candidate return address.
+pub(crate) const CALL_READ_LEN: u64 = 8;
+
+/// Whether `code`, the bytes right before a candidate return address, ends with a call
+/// instruction.
+///
+/// Only the instruction forms compilers emit for calls are recognized: `call rel32`, and
+/// `call r/m64` with any addressing mode.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn ends_with_call(code: [u8; CALL_READ_LEN as usize]) -> bool {
+    const CALL_REL32_LEN: usize = 5;
+    if code[code.len() - CALL_REL32_LEN] == 0xe8 {
+        return true;
+    }
+    // `ff /2`, from 2 bytes (`call *%rax`) to 7 bytes (`call *disp32(%rsp)`).
+    (2..=7).any(|len| {
+        let instruction = &code[code.len() - len..];
+        instruction[0] == 0xff
+            && (instruction[1] >> 3) & 7 == 2
+            && modrm_len(&instruction[1..]) == Some(len - 1)
+    })
+}
+
+/// The length of the ModRM byte at the start of `bytes` and of the SIB byte and displacement
+/// following it.
+#[cfg(target_arch = "x86_64")]
+fn modrm_len(bytes: &[u8]) -> Option<usize> {
+    let modrm = bytes[0];
+    let (mode, rm) = (modrm >> 6, modrm & 7);
+    if mode == 3 {
+        return Some(1);
+    }
+    let has_sib = rm == 4;
+    let displacement = match mode {
+        1 => 1,
+        2 => 4,
+        // `disp32(%rip)`, or a SIB byte without base register.
+        _ if rm == 5 => 4,
+        _ if has_sib && bytes.get(1)? & 7 == 5 => 4,
+        _ => 0,
+    };
+    Some(1 + usize::from(has_sib) + displacement)
+}
+
+/// Whether `code`, the bytes right before a candidate return address, ends with a call
+/// instruction: `bl`, `blr`, or one of the `blra*` variants authenticating the target.
+#[cfg(target_arch = "aarch64")]
+pub(crate) fn ends_with_call(code: [u8; CALL_READ_LEN as usize]) -> bool {
+    let instruction = u32::from_le_bytes([code[4], code[5], code[6], code[7]]);
+    let is_bl = instruction & 0xfc00_0000 == 0x9400_0000;
+    let is_blr = instruction & 0xffff_fc1f == 0xd63f_0000;
+    let is_blra = instruction & 0xfeff_f800 == 0xd63f_0800;
+    is_bl || is_blr || is_blra
+}
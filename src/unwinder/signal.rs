@@ -0,0 +1,70 @@
+//! Unwinding through the frames the kernel pushes to deliver a signal.
+//!
+//! A signal handler returns to a trampoline that calls `rt_sigreturn`, with the stack pointer
+//! at the signal frame, which holds the registers of the interrupted code.
+
+use super::regs::Regs;
+
+/// The code of the trampoline: `mov $15, %rax; syscall`.
+#[cfg(target_arch = "x86_64")]
+const TRAMPOLINE_CODE: &[u8] = &[0x48, 0xc7, 0xc0, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05];
+/// The code of the trampoline: `mov x8, #139; svc #0`.
+#[cfg(target_arch = "aarch64")]
+const TRAMPOLINE_CODE: &[u8] = &[0x68, 0x11, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4];
+
+/// Number of bytes read to recognize the trampoline.
+pub(crate) const TRAMPOLINE_READ_LEN: u64 = 16;
+
+// Offsets of the saved registers from the stack pointer at the trampoline. On x86_64, the
+// signal frame starts with the `ucontext_t`, whose `uc_mcontext.gregs` is at offset 40. On
+// aarch64, the `ucontext_t` follows a `siginfo_t`, and its `uc_mcontext` is at offset 176.
+#[cfg(target_arch = "x86_64")]
+mod offsets {
+    const GREGS: u64 = 40;
+    pub(super) const PC: u64 = GREGS + 16 * 8;
+    pub(super) const SP: u64 = GREGS + 15 * 8;
+    pub(super) const FP: u64 = GREGS + 10 * 8;
+}
+#[cfg(target_arch = "aarch64")]
+mod offsets {
+    const MCONTEXT: u64 = 128 + 176;
+    const REGS: u64 = MCONTEXT + 8;
+    pub(super) const PC: u64 = MCONTEXT + 264;
+    pub(super) const SP: u64 = MCONTEXT + 256;
+    pub(super) const FP: u64 = REGS + 29 * 8;
+    pub(super) const LR: u64 = REGS + 30 * 8;
+}
+
+/// Whether the code at `address` is the trampoline, reading it with `read_code`.
+pub(crate) fn is_trampoline(
+    address: u64,
+    mut read_code: impl FnMut(u64) -> Result<u64, ()>,
+) -> bool {
+    let (Ok(first), Ok(second)) = (read_code(address), read_code(address + 8)) else {
+        return false;
+    };
+    let mut code = [0; 16];
+    code[..8].copy_from_slice(&first.to_le_bytes());
+    code[8..].copy_from_slice(&second.to_le_bytes());
+    code.starts_with(TRAMPOLINE_CODE)
+}
+
+/// Reads the registers of the interrupted code from the signal frame at `sp`, the stack
+/// pointer at the trampoline.
+///
+/// Returns the address that could not be read on failure.
+pub(crate) fn saved_regs(
+    sp: u64,
+    mut read_stack: impl FnMut(u64) -> Result<u64, ()>,
+) -> Result<Regs, u64> {
+    let mut read = |offset| read_stack(sp + offset).map_err(|_| sp + offset);
+    Ok(Regs {
+        pc: read(offsets::PC)?,
+        sp: read(offsets::SP)?,
+        fp: read(offsets::FP)?,
+        #[cfg(target_arch = "x86_64")]
+        lr: 0,
+        #[cfg(target_arch = "aarch64")]
+        lr: read(offsets::LR)?,
+    })
+}
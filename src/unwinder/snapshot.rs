@@ -0,0 +1,29 @@
+//! Copies of a stack, taken at sample time and unwound later.
+
+use std::{ops::Range, path::Path, sync::Arc};
+
+/// The registers and the top of the stack of a thread, copied so that the stack can be
+/// unwound later, e.g. on another thread. Taking a snapshot is cheaper than unwinding.
+#[derive(Debug, Clone)]
+pub struct StackSnapshot {
+    pub pc: u64,
+    pub sp: u64,
+    pub fp: u64,
+    /// The link register, or 0 on architectures without one.
+    pub lr: u64,
+    /// Address of the first byte of `stack`, usually `sp`.
+    pub stack_base: u64,
+    pub stack: Vec<u8>,
+    /// The images mapped into the process when the snapshot was taken. If empty, the walk
+    /// uses the images known to the unwinder.
+    pub modules: Vec<SnapshotModule>,
+}
+
+/// An image mapped into the process when a [`StackSnapshot`] was taken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotModule {
+    pub path: Arc<Path>,
+    /// Difference between the addresses in the process and the addresses stated in the file.
+    pub bias: u64,
+    pub avma_range: Range<u64>,
+}
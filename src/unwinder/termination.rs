@@ -0,0 +1,64 @@
+use std::{fmt, path::Path, sync::Arc};
+
+/// Why a stack walk ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TerminationReason {
+    /// The root frame of the stack was reached, so the stack is complete.
+    EndOfStack,
+    /// The stack memory at `address` could not be read.
+    MemoryReadFailed { address: u64 },
+    /// The walk could not continue past `address`, which has no unwind information.
+    ///
+    /// `module` is the module containing `address`, if any. It is `None` if the address is
+    /// not inside any known module, e.g. JIT-compiled code or a corrupted return address.
+    MissingUnwindInfo {
+        address: u64,
+        module: Option<Arc<Path>>,
+    },
+    /// The maximum number of frames was reached. The stack may have more frames.
+    DepthLimit,
+    /// framehop gave up unwinding for another reason.
+    Unwind(framehop::Error),
+}
+
+impl TerminationReason {
+    /// Whether the stack is known to be complete.
+    pub fn is_complete(&self) -> bool {
+        matches!(self, Self::EndOfStack)
+    }
+}
+
+impl From<framehop::Error> for TerminationReason {
+    fn from(error: framehop::Error) -> Self {
+        match error {
+            framehop::Error::CouldNotReadStack(address) => Self::MemoryReadFailed { address },
+            error => Self::Unwind(error),
+        }
+    }
+}
+
+impl fmt::Display for TerminationReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EndOfStack => write!(f, "reached the end of the stack"),
+            Self::MemoryReadFailed { address } => {
+                write!(f, "could not read stack memory at 0x{address:x}")
+            }
+            Self::MissingUnwindInfo {
+                address,
+                module: Some(module),
+            } => write!(
+                f,
+                "no unwind information for 0x{address:x} in {}",
+                module.display()
+            ),
+            Self::MissingUnwindInfo {
+                address,
+                module: None,
+            } => write!(f, "no module contains 0x{address:x}"),
+            Self::DepthLimit => write!(f, "reached the maximum number of frames"),
+            Self::Unwind(error) => write!(f, "{error}"),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Capturing the registers of another thread of this process.
+//!
+//! The thread is interrupted with the real-time signal `SIGRTMIN + 4`, and its handler
+//! publishes the interrupted registers and then waits until the requesting thread has walked
+//! the stack. The handler is installed on first use, unless the signal already has one.
+
+use super::{regs::Regs, Frame, StackBounds, TerminationReason};
+use std::{
+    ffi::{c_int, c_void},
+    fmt, io,
+    ops::Range,
+    sync::{
+        atomic::{AtomicI32, AtomicU64, AtomicU8, Ordering},
+        Mutex, MutexGuard, Once,
+    },
+    time::{Duration, Instant},
+};
+
+/// Returns the kernel ID of the calling thread.
+///
+/// A thread can report its ID this way so that it can be captured from another thread,
+/// since neither `pthread_t` nor [`std::thread::Thread`] expose it.
+pub fn gettid() -> libc::pid_t {
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+}
+
+/// The stack of another thread of this process, see
+/// [`unwind_thread`](super::StackUnwinder::unwind_thread).
+#[derive(Debug, Clone)]
+pub struct ThreadCapture {
+    pub frames: Vec<Frame>,
+    /// Why the walk ended.
+    pub termination_reason: TerminationReason,
+}
+
+/// Why the stack of another thread could not be captured.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ThreadCaptureError {
+    /// There is no thread with this ID in this process.
+    NoSuchThread,
+    /// The calling thread can't capture itself this way; use `unwind` instead.
+    CurrentThread,
+    /// The thread did not handle the signal in time, e.g. because it blocks signals.
+    Timeout,
+    /// Another handler is installed for `SIGRTMIN + 4`, the signal used to interrupt the
+    /// thread.
+    SignalInUse,
+    /// Installing the signal handler or signalling the thread failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for ThreadCaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchThread => write!(f, "no such thread in this process"),
+            Self::CurrentThread => write!(f, "cannot capture the calling thread"),
+            Self::Timeout => write!(f, "the thread did not respond to the signal in time"),
+            Self::SignalInUse => write!(f, "SIGRTMIN+4 already has another handler"),
+            Self::Io(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for ThreadCaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ThreadCaptureError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+const IDLE: u8 = 0;
+/// The target has been signalled.
+const REQUESTED: u8 = 1;
+/// The handler is publishing the registers.
+const CAPTURING: u8 = 2;
+/// The registers are published and the handler waits for the requester.
+const CAPTURED: u8 = 3;
+/// The requester is done, and the handler may return.
+const RELEASED: u8 = 4;
+
+/// Only one thread is captured at a time.
+static CAPTURE_LOCK: Mutex<()> = Mutex::new(());
+static STATE: AtomicU8 = AtomicU8::new(IDLE);
+static TARGET: AtomicI32 = AtomicI32::new(0);
+static REGS: [AtomicU64; 4] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+/// The signal used to interrupt the target thread. Its handler is installed on first use.
+fn capture_signal() -> c_int {
+    libc::SIGRTMIN() + 4
+}
+
+extern "C" fn handle_capture_signal(_: c_int, _: *mut libc::siginfo_t, context: *mut c_void) {
+    if TARGET.load(Ordering::Acquire) != gettid()
+        || STATE
+            .compare_exchange(REQUESTED, CAPTURING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+    {
+        // A late signal for a request that has timed out.
+        return;
+    }
+    let regs = Regs::from_ucontext(unsafe { &*(context as *const libc::ucontext_t) });
+    for (slot, value) in REGS.iter().zip([regs.pc, regs.sp, regs.fp, regs.lr]) {
+        slot.store(value, Ordering::Relaxed);
+    }
+    STATE.store(CAPTURED, Ordering::Release);
+
+    // Keep the stack unchanged while it is being walked.
+    while STATE.load(Ordering::Acquire) != RELEASED {
+        unsafe { libc::sched_yield() };
+    }
+    STATE.store(IDLE, Ordering::Release);
+}
+
+/// Installs the handler of the capture signal, unless the signal has another disposition
+/// than the default one, e.g. a handler of the application.
+fn install_handler() -> Result<(), ThreadCaptureError> {
+    /// Stored in `RESULT` if the signal has another disposition.
+    const SIGNAL_IN_USE: i32 = -1;
+
+    static INSTALL: Once = Once::new();
+    /// 0 once installed, `SIGNAL_IN_USE`, or the error number of the failure.
+    static RESULT: AtomicI32 = AtomicI32::new(0);
+    INSTALL.call_once(|| unsafe {
+        let mut old: libc::sigaction = std::mem::zeroed();
+        let result = if libc::sigaction(capture_signal(), std::ptr::null(), &mut old) != 0 {
+            io::Error::last_os_error()
+                .raw_os_error()
+                .unwrap_or(libc::EINVAL)
+        } else if old.sa_sigaction != libc::SIG_DFL {
+            SIGNAL_IN_USE
+        } else {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_capture_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(capture_signal(), &action, std::ptr::null_mut()) != 0 {
+                io::Error::last_os_error()
+                    .raw_os_error()
+                    .unwrap_or(libc::EINVAL)
+            } else {
+                0
+            }
+        };
+        RESULT.store(result, Ordering::Relaxed);
+    });
+    match RESULT.load(Ordering::Relaxed) {
+        0 => Ok(()),
+        SIGNAL_IN_USE => Err(ThreadCaptureError::SignalInUse),
+        errno => Err(io::Error::from_raw_os_error(errno).into()),
+    }
+}
+
+/// Another thread kept waiting in the signal handler, so that its stack can be read from
+/// the requesting thread. The thread resumes when this is dropped.
+///
+/// The suspended thread may hold any lock, including the allocator's, so the requester must
+/// not allocate or take locks until it drops this.
+pub(crate) struct SuspendedThread {
+    pub(crate) regs: Regs,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl SuspendedThread {
+    pub(crate) fn suspend(tid: libc::pid_t, timeout: Duration) -> Result<Self, ThreadCaptureError> {
+        if tid == gettid() {
+            return Err(ThreadCaptureError::CurrentThread);
+        }
+        install_handler()?;
+        let guard = CAPTURE_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        TARGET.store(tid, Ordering::Release);
+        STATE.store(REQUESTED, Ordering::Release);
+        let result =
+            unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), tid, capture_signal()) };
+        if result != 0 {
+            let error = io::Error::last_os_error();
+            STATE.store(IDLE, Ordering::Release);
+            return Err(match error.raw_os_error() {
+                Some(libc::ESRCH) => ThreadCaptureError::NoSuchThread,
+                _ => error.into(),
+            });
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            match STATE.load(Ordering::Acquire) {
+                CAPTURED => break,
+                // Once the handler has started, it finishes quickly.
+                REQUESTED
+                    if Instant::now() >= deadline
+                        && STATE
+                            .compare_exchange(REQUESTED, IDLE, Ordering::AcqRel, Ordering::Acquire)
+                            .is_ok() =>
+                {
+                    return Err(ThreadCaptureError::Timeout);
+                }
+                _ => std::thread::yield_now(),
+            }
+        }
+
+        let [pc, sp, fp, lr] = [0, 1, 2, 3].map(|index| REGS[index].load(Ordering::Relaxed));
+        Ok(Self {
+            regs: Regs { pc, sp, fp, lr },
+            _guard: guard,
+        })
+    }
+}
+
+impl Drop for SuspendedThread {
+    fn drop(&mut self) {
+        STATE.store(RELEASED, Ordering::Release);
+        // Wait for the handler to return before the next capture can start.
+        while STATE.load(Ordering::Acquire) != IDLE {
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// Returns the address ranges of the mappings of this process.
+pub(crate) fn mappings() -> io::Result<Vec<Range<u64>>> {
+    let maps = std::fs::read_to_string("/proc/self/maps")?;
+    Ok(maps
+        .lines()
+        .filter_map(|line| {
+            let (start, end) = line.split_whitespace().next()?.split_once('-')?;
+            let start = u64::from_str_radix(start, 16).ok()?;
+            let end = u64::from_str_radix(end, 16).ok()?;
+            Some(start..end)
+        })
+        .collect())
+}
+
+/// Returns the part of the stack above `sp`, from the mapping containing it.
+pub(crate) fn stack_bounds(mappings: &[Range<u64>], sp: u64) -> Option<StackBounds> {
+    let mapping = mappings.iter().find(|mapping| mapping.contains(&sp))?;
+    Some(StackBounds::new(sp, mapping.end))
+}
@@ -0,0 +1,121 @@
+//! Stack usage of walked frames, from the stack pointers the walk computes.
+
+use super::{Frame, FrameKind};
+use std::{collections::HashMap, hash::Hash};
+
+/// Returns the number of bytes of stack used by each frame of a walk, in the order of
+/// `frames`: from its stack pointer up to the one of its caller, so including the return
+/// address.
+///
+/// The size is `None` for the last frame, whose caller is unknown, and for frames whose
+/// caller may be on another stack, e.g. signal handlers.
+pub fn frame_sizes(frames: &[Frame]) -> Vec<Option<u64>> {
+    let mut sizes: Vec<Option<u64>> = frames
+        .windows(2)
+        .map(|pair| match pair[1].kind() {
+            FrameKind::InterruptedBySignal => None,
+            _ => pair[1].stack_pointer().checked_sub(pair[0].stack_pointer()),
+        })
+        .collect();
+    if !frames.is_empty() {
+        sizes.push(None);
+    }
+    sizes
+}
+
+/// Returns the number of bytes of stack used by a walk, the sum of its known
+/// [frame sizes](frame_sizes).
+pub fn stack_depth(frames: &[Frame]) -> u64 {
+    frame_sizes(frames).into_iter().flatten().sum()
+}
+
+/// The stack used by the frames of a function, see [`StackUsage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FunctionStackUsage {
+    /// Number of frames of the function with a known size.
+    pub frames: u64,
+    /// Total size of these frames.
+    pub total_bytes: u64,
+    /// Size of the largest of these frames.
+    pub max_frame_bytes: u64,
+    /// Largest stack used by the function in a single walk, e.g. by all the levels of a
+    /// recursion.
+    pub max_walk_bytes: u64,
+}
+
+/// Aggregates the stack used by each function across many walks, e.g. to find out which
+/// functions of a deep recursion use the most stack.
+///
+/// Frames are grouped by a key chosen by the caller, e.g. the name of the function found with
+/// a symbol map, or `address_for_lookup` to group them by call site.
+#[derive(Debug, Clone)]
+pub struct StackUsage<K> {
+    functions: HashMap<K, FunctionStackUsage>,
+    walks: u64,
+    max_depth: u64,
+}
+
+impl<K: Eq + Hash> Default for StackUsage<K> {
+    fn default() -> Self {
+        Self {
+            functions: HashMap::new(),
+            walks: 0,
+            max_depth: 0,
+        }
+    }
+}
+
+impl<K: Eq + Hash> StackUsage<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the frames of a walk, grouped with `function`. Frames for which it returns `None`,
+    /// and frames of unknown size, are left out.
+    pub fn add(&mut self, frames: &[Frame], mut function: impl FnMut(&Frame) -> Option<K>) {
+        let mut walk: HashMap<K, FunctionStackUsage> = HashMap::new();
+        let mut depth = 0;
+        for (frame, size) in frames.iter().zip(frame_sizes(frames)) {
+            let Some(size) = size else {
+                continue;
+            };
+            depth += size;
+            let Some(key) = function(frame) else {
+                continue;
+            };
+            let usage = walk.entry(key).or_default();
+            usage.frames += 1;
+            usage.total_bytes += size;
+            usage.max_frame_bytes = usage.max_frame_bytes.max(size);
+        }
+        for (key, walk_usage) in walk {
+            let usage = self.functions.entry(key).or_default();
+            usage.frames += walk_usage.frames;
+            usage.total_bytes += walk_usage.total_bytes;
+            usage.max_frame_bytes = usage.max_frame_bytes.max(walk_usage.max_frame_bytes);
+            usage.max_walk_bytes = usage.max_walk_bytes.max(walk_usage.total_bytes);
+        }
+        self.walks += 1;
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    /// Returns the stack used by the frames grouped under `key`.
+    pub fn get(&self, key: &K) -> Option<&FunctionStackUsage> {
+        self.functions.get(key)
+    }
+
+    /// Returns the stack used by each function, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &FunctionStackUsage)> {
+        self.functions.iter()
+    }
+
+    /// Number of walks added.
+    pub fn walks(&self) -> u64 {
+        self.walks
+    }
+
+    /// Largest [stack depth](stack_depth) of the walks added.
+    pub fn max_depth(&self) -> u64 {
+        self.max_depth
+    }
+}
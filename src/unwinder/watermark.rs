@@ -0,0 +1,150 @@
+//! Measuring how deep the stack of a thread gets, to size thread stacks empirically.
+//!
+//! The unused part of the stack is painted with a pattern, and the deepest word that no
+//! longer holds it marks the deepest point the stack has reached since. A periodic sampler
+//! can add the walks it takes, so that the deepest one tells which code got there.
+
+use super::{
+    regs::{current_regs, Regs},
+    Frame, StackBounds,
+};
+#[cfg(target_os = "linux")]
+use super::{StackUnwinder, ThreadCaptureError};
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+/// The word written over the unused part of a stack.
+const PAINT: u64 = 0xcdcd_cdcd_cdcd_cdcd;
+
+/// Bytes below the stack pointer left unpainted when painting, for the red zone and the
+/// frames of the painting itself.
+const PAINT_MARGIN: u64 = 4096;
+
+/// The high-water mark of the stack of a thread, see [`paint`](Self::paint).
+#[derive(Debug, Clone)]
+pub struct StackWatermark {
+    stack: StackBounds,
+    /// The painted part of the stack is `painted_start..painted_end`, aligned to words.
+    painted_start: u64,
+    painted_end: u64,
+    /// The added walk with the lowest stack pointer.
+    deepest_walk: Vec<Frame>,
+}
+
+impl StackWatermark {
+    /// Paints the unused part of `stack`, the stack of the calling thread, below the current
+    /// stack pointer. Returns `None` if the stack pointer is not in `stack`.
+    ///
+    /// The stack of the main thread grows on demand, and painting it would commit it
+    /// entirely, so only paint the stacks of threads such as the ones spawned with
+    /// [`std::thread`].
+    ///
+    /// # Safety
+    ///
+    /// `stack` must be the stack of the calling thread, as returned by
+    /// [`StackBounds::current_thread`], and be mapped and writable entirely. Everything below
+    /// the stack pointer is overwritten.
+    #[inline(never)]
+    pub unsafe fn paint(stack: StackBounds) -> Option<Self> {
+        let Regs { sp, .. } = current_regs!();
+        if !stack.contains(sp, 1) {
+            return None;
+        }
+        let painted_start = (stack.start + 7) & !7;
+        let painted_end = (sp.saturating_sub(PAINT_MARGIN) & !7).max(painted_start);
+        for address in (painted_start..painted_end).step_by(8) {
+            unsafe { std::ptr::write_volatile(address as *mut u64, PAINT) };
+        }
+        Some(Self {
+            stack,
+            painted_start,
+            painted_end,
+            deepest_walk: Vec::new(),
+        })
+    }
+
+    pub fn stack(&self) -> StackBounds {
+        self.stack
+    }
+
+    /// Returns the lowest address of the stack written since it was painted, or the end of
+    /// the painted part if none was.
+    ///
+    /// # Safety
+    ///
+    /// The stack is read directly, so it must still be mapped, e.g. the thread must not have
+    /// exited and been joined. Unless this is called on the thread itself, the thread must
+    /// also not write to its stack meanwhile: it must be suspended, or have stopped using the
+    /// stack, e.g. blocked until it is told to exit.
+    pub unsafe fn deepest_touched_address(&self) -> u64 {
+        (self.painted_start..self.painted_end)
+            .step_by(8)
+            .find(|&address| unsafe { std::ptr::read_volatile(address as *const u64) } != PAINT)
+            .unwrap_or(self.painted_end)
+    }
+
+    /// Returns the number of bytes of the stack used at its deepest point since it was
+    /// painted, see [`deepest_touched_address`](Self::deepest_touched_address).
+    ///
+    /// This includes the signal handlers that ran on the stack, such as the one of
+    /// [`sample_thread`](Self::sample_thread), so sampling inflates it by a few kilobytes.
+    ///
+    /// # Safety
+    ///
+    /// As for [`deepest_touched_address`](Self::deepest_touched_address), the stack must
+    /// still be mapped, and not be written to by another thread meanwhile.
+    pub unsafe fn max_used_bytes(&self) -> u64 {
+        self.stack.end - unsafe { self.deepest_touched_address() }
+    }
+
+    /// Adds a walk of the stack, e.g. taken by a periodic sampler. The one with the lowest
+    /// stack pointer in its first frame is kept, see [`deepest_walk`](Self::deepest_walk).
+    /// Walks of other stacks are ignored.
+    pub fn add_walk(&mut self, frames: &[Frame]) {
+        let Some(sp) = frames.first().map(Frame::stack_pointer) else {
+            return;
+        };
+        if !self.stack.contains(sp, 1) {
+            return;
+        }
+        let is_deeper = match self.deepest_walk.first() {
+            Some(deepest) => sp < deepest.stack_pointer(),
+            None => true,
+        };
+        if is_deeper {
+            self.deepest_walk.clear();
+            self.deepest_walk.extend_from_slice(frames);
+        }
+    }
+
+    /// Walks the stack of the thread `tid` with
+    /// [`unwind_thread`](super::StackUnwinder::unwind_thread) and adds the walk.
+    ///
+    /// The handler of `SIGRTMIN + 4`, installed on first use, keeps the thread waiting during
+    /// the walk. It runs on the stack of the thread, so each sample touches a few kilobytes
+    /// below the stack pointer of the thread, which inflates
+    /// [`max_used_bytes`](Self::max_used_bytes) by as much. The walk starts at the interrupted
+    /// code, so the frames of the handler don't count towards the deepest walk.
+    #[cfg(target_os = "linux")]
+    pub fn sample_thread(
+        &mut self,
+        unwinder: &mut StackUnwinder,
+        tid: libc::pid_t,
+        timeout: Duration,
+    ) -> Result<(), ThreadCaptureError> {
+        let capture = unwinder.unwind_thread(tid, timeout)?;
+        self.add_walk(&capture.frames);
+        Ok(())
+    }
+
+    /// Returns the deepest walk added, which was active when the stack was observed at its
+    /// deepest. The stack may have been deeper between samples, see
+    /// [`deepest_touched_address`](Self::deepest_touched_address).
+    pub fn deepest_walk(&self) -> Option<&[Frame]> {
+        if self.deepest_walk.is_empty() {
+            None
+        } else {
+            Some(&self.deepest_walk)
+        }
+    }
+}
@@ -1,80 +1,101 @@
 use framehop::{
     x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64},
-    FrameAddress, Unwinder,
+    MustNotAllocateDuringUnwind,
 };
-use std::arch::asm;
 
-/// load libraries, configure cache or unwinder, etc.
-#[derive(Default)]
-pub struct UnwindBuilderX86_64 {}
+use super::{
+    live::{imp::LiveArchImpl, LiveArch, LiveStackUnwinder, LiveUnwindBuilder, LiveUnwindIterator},
+    reader::StackBounds,
+    regs::Regs,
+    ModuleData,
+};
+
+/// The x86_64 architecture, for [`LiveStackUnwinder`].
+pub enum LiveX86_64 {}
+
+impl LiveArch for LiveX86_64 {}
+
+impl LiveArchImpl for LiveX86_64 {
+    type Regs = UnwindRegsX86_64;
+    type Cache = CacheX86_64<MustNotAllocateDuringUnwind>;
+    type Unwinder = UnwinderX86_64<ModuleData, MustNotAllocateDuringUnwind>;
+    type Config = ();
+
+    const SCANNED_RETURN_ADDRESS_IN_FRAME_RECORD: bool = false;
+
+    fn default_config() {}
 
-impl UnwindBuilderX86_64 {
-    pub fn new() -> Self {
-        Self::default()
+    fn new_unwinder() -> Self::Unwinder {
+        UnwinderX86_64::new()
     }
-    pub fn build(self) -> StackUnwinderX86_64 {
-        StackUnwinderX86_64 {
-            cache: CacheX86_64::<_>::new(),
-            unwinder: UnwinderX86_64::new(),
-            closure: Box::new(|addr: u64| {
-                // Unaligned address
-                assert!(addr % 8 == 0);
-                unsafe { Ok(*(addr as *const u64)) }
-            }),
-        }
+
+    fn new_cache() -> Self::Cache {
+        CacheX86_64::new_in()
     }
-}
 
-pub struct StackUnwinderX86_64 {
-    cache: CacheX86_64,
-    unwinder: UnwinderX86_64<Vec<u8>>,
-    closure: Box<dyn FnMut(u64) -> Result<u64, ()>>,
-}
+    fn regs((): (), pc: u64, sp: u64, fp: u64, _lr: u64) -> UnwindRegsX86_64 {
+        UnwindRegsX86_64::new(pc, sp, fp)
+    }
 
-impl StackUnwinderX86_64 {
-    pub fn unwind(&mut self) -> UnwindIterator<'_> {
-        #[allow(unused)]
-        let (rip, regs) = {
-            let mut rip = 0;
-            let mut rsp = 0;
-            let mut rbp = 0;
-            unsafe {
-                asm!("lea {}, [rip]", out(reg) rip);
-                asm!("mov {}, rsp", out(reg) rsp);
-                asm!("mov {}, rbp", out(reg) rbp);
-            }
-            (rip, UnwindRegsX86_64::new(rip, rsp, rbp))
-        };
-
-        let iter = self
-            .unwinder
-            .iter_frames(rip, regs, &mut self.cache, &mut self.closure);
-
-        UnwindIterator::new(iter)
+    fn sp(regs: &UnwindRegsX86_64) -> u64 {
+        regs.sp()
     }
-}
 
-pub type UnwindIteratorX86_64<'a> = framehop::UnwindIterator<
-    'a,
-    'a,
-    'a,
-    UnwinderX86_64<Vec<u8>>,
-    Box<dyn FnMut(u64) -> Result<u64, ()>>,
->;
+    fn fp(regs: &UnwindRegsX86_64) -> u64 {
+        regs.bp()
+    }
 
-pub struct UnwindIterator<'a> {
-    inner: UnwindIteratorX86_64<'a>,
-}
+    fn set_sp(regs: &mut UnwindRegsX86_64, sp: u64) {
+        regs.set_sp(sp);
+    }
 
-impl<'a> UnwindIterator<'a> {
-    fn new(inner: UnwindIteratorX86_64<'a>) -> Self {
-        Self { inner }
+    fn set_fp(regs: &mut UnwindRegsX86_64, fp: u64) {
+        regs.set_bp(fp);
+    }
+
+    fn strip_return_address((): (), address: u64) -> u64 {
+        address
+    }
+
+    fn caller_before_frame_setup(
+        regs: &UnwindRegsX86_64,
+        read_stack: &mut dyn FnMut(u64) -> Result<u64, ()>,
+    ) -> Result<(u64, u64), u64> {
+        // The return address was just pushed by the call.
+        let sp = regs.sp();
+        let return_address = read_stack(sp).map_err(|_| sp)?;
+        Ok((return_address, sp + 8))
     }
 }
 
-impl<'a> Iterator for UnwindIterator<'a> {
-    type Item = FrameAddress;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().ok().flatten()
+pub type UnwindBuilderX86_64 = LiveUnwindBuilder<LiveX86_64>;
+pub type StackUnwinderX86_64 = LiveStackUnwinder<LiveX86_64>;
+pub type UnwindIterator<'a> = LiveUnwindIterator<'a, LiveX86_64>;
+
+impl StackUnwinderX86_64 {
+    /// Unwinds the stack of the current thread starting from the given register values,
+    /// e.g. ones saved by a signal handler. `pc` is the first frame.
+    pub fn unwind_from_regs(&mut self, pc: u64, sp: u64, fp: u64) -> UnwindIterator<'_> {
+        self.iter_frames(Regs { pc, sp, fp, lr: 0 }, 0)
+    }
+
+    /// Unwinds the stack of a suspended fiber or coroutine from the registers saved when it
+    /// was switched out, without switching to it. `pc` is the first frame.
+    ///
+    /// Only `stack`, the memory allocated for the stack of the fiber, is read, so a stale or
+    /// corrupted context ends the walk instead of crashing the process.
+    ///
+    /// # Safety
+    ///
+    /// The walk reads `stack` directly, so it must be mapped, readable memory until the
+    /// returned iterator is dropped, e.g. the fiber must not be freed meanwhile.
+    pub unsafe fn unwind_fiber(
+        &mut self,
+        pc: u64,
+        sp: u64,
+        fp: u64,
+        stack: StackBounds,
+    ) -> UnwindIterator<'_> {
+        unsafe { self.fiber(Regs { pc, sp, fp, lr: 0 }, stack) }
     }
 }
@@ -0,0 +1,47 @@
+//! The vDSO, a shared library the kernel maps into every process, without a file on disk.
+
+use object::{
+    elf::{FileHeader64, PT_LOAD},
+    read::elf::{FileHeader, ProgramHeader},
+    Endianness,
+};
+
+/// The path of the vDSO, as shown in `/proc/<pid>/maps`.
+pub(crate) const PATH: &str = "[vdso]";
+
+/// Returns the address the vDSO of this process is mapped at, or 0 if there is none.
+pub(crate) fn base() -> u64 {
+    unsafe { libc::getauxval(libc::AT_SYSINFO_EHDR) }
+}
+
+/// Returns the ELF image of the vDSO of this process.
+///
+/// The kernel maps the whole image, section headers included, so it can be parsed like a
+/// file.
+pub(crate) fn image() -> Option<&'static [u8]> {
+    let base = base() as *const u8;
+    if base.is_null() {
+        return None;
+    }
+    let header_len = std::mem::size_of::<FileHeader64<Endianness>>();
+    let header = unsafe { std::slice::from_raw_parts(base, header_len) };
+    let header = FileHeader64::<Endianness>::parse(header).ok()?;
+    let endian = header.endian().ok()?;
+
+    // The program headers follow the file header.
+    let phdrs_end = header.e_phoff(endian)
+        + u64::from(header.e_phnum(endian)) * u64::from(header.e_phentsize(endian));
+    let data = unsafe { std::slice::from_raw_parts(base, phdrs_end as usize) };
+    let segments_end = header
+        .program_headers(endian, data)
+        .ok()?
+        .iter()
+        .filter(|phdr| phdr.p_type(endian) == PT_LOAD)
+        .map(|phdr| phdr.p_offset(endian) + phdr.p_filesz(endian))
+        .max()?;
+    let shdrs_end = header.e_shoff(endian)
+        + u64::from(header.e_shnum(endian)) * u64::from(header.e_shentsize(endian));
+
+    let len = segments_end.max(shdrs_end).max(phdrs_end);
+    Some(unsafe { std::slice::from_raw_parts(base, len as usize) })
+}
@@ -3,10 +3,10 @@ use hopframe::unwinder::UnwindBuilder;
 #[inline(never)]
 pub fn test_function_level_3() -> Vec<u64> {
     let mut unwinder = UnwindBuilder::new().build();
-    let mut iter = unwinder.unwind();
+    let iter = unwinder.unwind();
     let mut addresses = Vec::new();
 
-    while let Some(frame) = iter.next() {
+    for frame in iter {
         addresses.push(frame.address_for_lookup());
     }
 
@@ -19,10 +19,10 @@ async fn verify_function_symbols_in_backtrace() {
     #[inline(never)]
     fn test_level_3_with_unwinder() -> Vec<u64> {
         let mut unwinder = UnwindBuilder::new().build();
-        let mut iter = unwinder.unwind();
+        let iter = unwinder.unwind();
         let mut addresses = Vec::new();
 
-        while let Some(frame) = iter.next() {
+        for frame in iter {
             addresses.push(frame.address_for_lookup());
         }
 
@@ -97,10 +97,10 @@ async fn verify_recursive_function_symbols() {
     fn recursive_with_unwinder(current: u32, max_depth: u32) -> Vec<u64> {
         if current >= max_depth {
             let mut unwinder = UnwindBuilder::new().build();
-            let mut iter = unwinder.unwind();
+            let iter = unwinder.unwind();
             let mut addresses = Vec::new();
 
-            while let Some(frame) = iter.next() {
+            for frame in iter {
                 addresses.push(frame.address_for_lookup());
             }
 
@@ -141,10 +141,10 @@ async fn test_symbol_resolution_consistency() {
     #[inline(never)]
     fn unique_test_function_a() -> Vec<u64> {
         let mut unwinder = UnwindBuilder::new().build();
-        let mut iter = unwinder.unwind();
+        let iter = unwinder.unwind();
         let mut addresses = Vec::new();
 
-        while let Some(frame) = iter.next() {
+        for frame in iter {
             addresses.push(frame.address_for_lookup());
         }
 
@@ -256,3 +256,49 @@ async fn test_common_test_functions_symbolization() {
         found_functions
     );
 }
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_vdso_symbols() {
+    use hopframe::aslr::read_vdso_offset;
+
+    #[cfg(target_arch = "x86_64")]
+    const CLOCK_GETTIME: &[u8] = b"__vdso_clock_gettime\0";
+    #[cfg(target_arch = "aarch64")]
+    const CLOCK_GETTIME: &[u8] = b"__kernel_clock_gettime\0";
+
+    // qemu-user before 8.1 doesn't map a vDSO.
+    if unsafe { libc::getauxval(libc::AT_SYSINFO_EHDR) } == 0 {
+        let symbol_map = SymbolMapBuilder::new().with_vdso().build().await;
+        assert!(symbol_map
+            .lookup(LookupAddress::Relative(0))
+            .await
+            .is_none());
+        eprintln!("Skipped: no vDSO in this process");
+        return;
+    }
+
+    let vdso = unsafe {
+        libc::dlopen(
+            b"linux-vdso.so.1\0".as_ptr() as *const libc::c_char,
+            libc::RTLD_LAZY | libc::RTLD_NOLOAD,
+        )
+    };
+    assert!(!vdso.is_null(), "The vDSO should be loaded");
+    let clock_gettime =
+        unsafe { libc::dlsym(vdso, CLOCK_GETTIME.as_ptr() as *const libc::c_char) } as u64;
+
+    let symbol_map = SymbolMapBuilder::new().with_vdso().build().await;
+    let vdso_offset = read_vdso_offset().unwrap();
+    let symbol = symbol_map
+        .lookup(LookupAddress::Relative(
+            (clock_gettime - vdso_offset) as u32,
+        ))
+        .await
+        .expect("Should find the symbol of clock_gettime");
+    assert!(
+        symbol.symbol.name.contains("clock_gettime"),
+        "Unexpected symbol: {}",
+        symbol.symbol.name
+    );
+}
@@ -0,0 +1,1663 @@
+use hopframe::unwinder::{StackBounds, TerminationReason, UnwindBuilder};
+use std::hint::black_box;
+
+#[test]
+fn test_unwind_through_recursion() {
+    #[inline(never)]
+    fn recursive_frame_count(current: u32, max_depth: u32) -> usize {
+        if current >= max_depth {
+            let mut unwinder = UnwindBuilder::new().build();
+            unwinder.unwind().count()
+        } else {
+            black_box(recursive_frame_count(current + 1, max_depth))
+        }
+    }
+
+    let depth = 20;
+    let frames = recursive_frame_count(0, depth);
+    assert!(
+        frames > depth as usize,
+        "Should unwind past every recursion level. Found {} frames",
+        frames
+    );
+}
+
+#[test]
+fn test_stack_usage() {
+    use hopframe::unwinder::{frame_sizes, stack_depth, Frame, StackUsage};
+
+    const BUFFER_SIZE: usize = 1024;
+
+    #[inline(never)]
+    fn recurse(current: u32, max_depth: u32) -> Vec<Frame> {
+        let buffer = black_box([current as u8; BUFFER_SIZE]);
+        if current >= max_depth {
+            UnwindBuilder::new().build().unwind().collect()
+        } else {
+            let frames = black_box(recurse(current + 1, max_depth));
+            black_box(&buffer);
+            frames
+        }
+    }
+
+    let depth = 10;
+    let frames = recurse(0, depth);
+    let sizes = frame_sizes(&frames);
+    assert_eq!(sizes.len(), frames.len());
+    assert_eq!(sizes.last(), Some(&None));
+
+    // Every level of the recursion returns to the same call site, with a frame holding the
+    // buffer.
+    let call_site = frames[2].address_for_lookup();
+    let levels: Vec<u64> = frames
+        .iter()
+        .zip(&sizes)
+        .filter(|(frame, _)| frame.address_for_lookup() == call_site)
+        .map(|(_, size)| size.expect("The frame should have a caller"))
+        .collect();
+    assert_eq!(levels.len(), depth as usize);
+    assert!(levels.iter().all(|&size| size >= BUFFER_SIZE as u64));
+    assert!(stack_depth(&frames) >= levels.iter().sum());
+
+    let mut usage = StackUsage::new();
+    usage.add(&frames, |frame| Some(frame.address_for_lookup()));
+    usage.add(&frames[..frames.len() / 2], |frame| {
+        Some(frame.address_for_lookup())
+    });
+    let recursion = usage.get(&call_site).expect("Should find the call site");
+    assert_eq!(recursion.max_walk_bytes, levels.iter().sum());
+    assert_eq!(recursion.max_frame_bytes, *levels.iter().max().unwrap());
+    assert!(recursion.frames > depth as u64);
+    assert_eq!(usage.walks(), 2);
+    assert_eq!(usage.max_depth(), stack_depth(&frames));
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[test]
+fn test_current_thread_stack_bounds() {
+    let local = 0u64;
+    let bounds = StackBounds::current_thread().expect("Should find the stack of this thread");
+    assert!(bounds.contains(&local as *const u64 as u64, 8));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_on_another_thread() {
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames = std::thread::spawn(move || unwinder.unwind().count())
+        .join()
+        .unwrap();
+    assert!(
+        frames > 2,
+        "Should read the stack of the thread the unwinder is used on. Found {} frames",
+        frames
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_through_shared_library() {
+    use std::ffi::{c_int, c_void, CStr};
+    use std::sync::Mutex;
+
+    static ADDRESSES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+    // Called back from `qsort`, so the stack goes through libc.
+    extern "C" fn compare(a: *const c_void, b: *const c_void) -> c_int {
+        let mut addresses = ADDRESSES.lock().unwrap();
+        if addresses.is_empty() {
+            let mut unwinder = UnwindBuilder::new().build();
+            addresses.extend(unwinder.unwind().map(|frame| frame.address_for_lookup()));
+        }
+        unsafe { *(a as *const u32) }.cmp(&unsafe { *(b as *const u32) }) as c_int
+    }
+
+    fn module_name(address: u64) -> String {
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        if unsafe { libc::dladdr(address as *const c_void, &mut info) } == 0
+            || info.dli_fname.is_null()
+        {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr(info.dli_fname) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    let mut values = [3u32, 1, 2];
+    unsafe {
+        libc::qsort(
+            values.as_mut_ptr() as *mut c_void,
+            values.len(),
+            std::mem::size_of::<u32>(),
+            Some(compare),
+        )
+    };
+
+    let modules: Vec<String> = ADDRESSES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|&address| module_name(address))
+        .collect();
+    let libc_frame = modules
+        .iter()
+        .position(|name| name.contains("libc"))
+        .expect("Should find a frame inside libc");
+    let exe = std::env::current_exe().unwrap();
+    assert!(
+        modules[libc_frame..]
+            .iter()
+            .any(|name| std::path::Path::new(name) == exe),
+        "Should unwind through libc back into the executable. Found: {:?}",
+        modules
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_info_from_memory() {
+    // Both walks start from the same code, so they find the same frames.
+    let walks: Vec<(Vec<u64>, Option<TerminationReason>)> = [false, true]
+        .into_iter()
+        .map(|from_memory| {
+            let mut unwinder = UnwindBuilder::new()
+                .with_unwind_info_from_memory(from_memory)
+                .build();
+            let mut iter = unwinder.unwind();
+            let frames = iter.by_ref().map(|frame| frame.address()).collect();
+            (frames, iter.termination_reason().cloned())
+        })
+        .collect();
+    assert_eq!(walks[1], walks[0]);
+    assert_eq!(walks[1].1, Some(TerminationReason::EndOfStack));
+}
+
+/// Set for the child process of `test_unwind_info_from_memory_without_proc`.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+const WITHOUT_PROC_ENV: &str = "HOPFRAME_TEST_WITHOUT_PROC";
+
+/// Exit status of the child process when it can't hide `/proc`.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+const WITHOUT_PROC_SKIPPED: i32 = 77;
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[test]
+fn test_unwind_info_from_memory_without_proc() {
+    // glibc only needs `/proc` to find the stack of the main thread, and the test harness runs
+    // tests on other threads. The child walks its main thread before the harness starts.
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .env(WITHOUT_PROC_ENV, "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.code() == Some(WITHOUT_PROC_SKIPPED) {
+        eprintln!("Skipped: {}", stderr);
+        return;
+    }
+    assert!(output.status.success(), "{}: {}", output.status, stderr);
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[used]
+#[link_section = ".init_array"]
+static UNWIND_MAIN_THREAD_WITHOUT_PROC: extern "C" fn() = unwind_main_thread_without_proc;
+
+/// Hides `/proc` and walks the main thread, in the child process of
+/// `test_unwind_info_from_memory_without_proc`.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+extern "C" fn unwind_main_thread_without_proc() {
+    if std::env::var_os(WITHOUT_PROC_ENV).is_none() {
+        return;
+    }
+    unsafe {
+        // Mounting over `/proc` needs a mount namespace, and a user namespace if not root.
+        let flags = if libc::geteuid() == 0 {
+            libc::CLONE_NEWNS
+        } else {
+            libc::CLONE_NEWUSER | libc::CLONE_NEWNS
+        };
+        let null = std::ptr::null();
+        if libc::unshare(flags) != 0
+            || libc::mount(
+                null,
+                b"/\0".as_ptr().cast(),
+                null,
+                libc::MS_REC | libc::MS_PRIVATE,
+                null.cast(),
+            ) != 0
+            || libc::mount(
+                b"none\0".as_ptr().cast(),
+                b"/proc\0".as_ptr().cast(),
+                b"tmpfs\0".as_ptr().cast(),
+                0,
+                null.cast(),
+            ) != 0
+        {
+            eprintln!("Can't hide /proc: {}", std::io::Error::last_os_error());
+            libc::_exit(WITHOUT_PROC_SKIPPED);
+        }
+    }
+    assert!(!std::path::Path::new("/proc/self/maps").exists());
+
+    let stack = StackBounds::current_thread().expect("Should find the stack without /proc");
+    let local = 0u64;
+    assert!(stack.contains(&local as *const u64 as u64, 8));
+
+    let mut unwinder = UnwindBuilder::new()
+        .with_unwind_info_from_memory(true)
+        .build();
+    let mut iter = unwinder.unwind();
+    let frames = iter.by_ref().count();
+    assert!(frames > 1);
+    assert_eq!(
+        iter.termination_reason(),
+        Some(&TerminationReason::EndOfStack)
+    );
+
+    // Without a stack size limit, walks from deeper than where the unwinder was built must
+    // still reach the end of the stack.
+    #[inline(never)]
+    fn walk_deeper(
+        unwinder: &mut hopframe::unwinder::StackUnwinder,
+        depth: u32,
+    ) -> (usize, TerminationReason) {
+        let buffer = black_box([0u8; 4096]);
+        if depth > 0 {
+            let walk = walk_deeper(unwinder, depth - 1);
+            black_box(&buffer);
+            return walk;
+        }
+        let mut iter = unwinder.unwind();
+        let frames = iter.by_ref().count();
+        (frames, iter.termination_reason().unwrap().clone())
+    }
+
+    let unlimited = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_STACK, &unlimited) } != 0 {
+        eprintln!(
+            "Can't remove the stack size limit: {}",
+            std::io::Error::last_os_error()
+        );
+        unsafe { libc::_exit(WITHOUT_PROC_SKIPPED) };
+    }
+    let mut unwinder = UnwindBuilder::new()
+        .with_unwind_info_from_memory(true)
+        .build();
+    let (deeper_frames, reason) = walk_deeper(&mut unwinder, 16);
+    assert_eq!(reason, TerminationReason::EndOfStack);
+    assert!(deeper_frames > frames + 16);
+    unsafe { libc::_exit(0) };
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_termination_reason() {
+    let mut unwinder = UnwindBuilder::new().build();
+    let mut iter = unwinder.unwind();
+    assert!(iter.termination_reason().is_none());
+
+    let frames = iter.by_ref().count();
+    let reason = iter
+        .termination_reason()
+        .expect("Should know why the walk ended");
+    assert!(frames > 2);
+    assert!(
+        reason.is_complete(),
+        "Should unwind the whole stack. Stopped because: {}",
+        reason
+    );
+}
+
+#[test]
+fn test_max_frames() {
+    let mut unwinder = UnwindBuilder::new().with_max_frames(2).build();
+    let mut iter = unwinder.unwind();
+    assert_eq!(iter.by_ref().count(), 2);
+    assert_eq!(
+        iter.termination_reason(),
+        Some(&TerminationReason::DepthLimit)
+    );
+}
+
+#[test]
+fn test_skip_frames() {
+    #[inline(never)]
+    fn collect_frames(builder: UnwindBuilder) -> Vec<u64> {
+        let mut unwinder = builder.build();
+        let frames = unwinder
+            .unwind()
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        frames
+    }
+
+    let walks: Vec<Vec<u64>> = [
+        UnwindBuilder::new(),
+        UnwindBuilder::new().with_skip_internal_frame(true),
+        UnwindBuilder::new().with_skip_frames(2),
+    ]
+    .into_iter()
+    .map(collect_frames)
+    .collect();
+
+    assert_eq!(walks[1], walks[0][1..]);
+    assert_eq!(walks[2], walks[0][2..]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_from_ucontext() {
+    use std::sync::Mutex;
+
+    static SIGNAL_FRAMES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+    extern "C" fn handler(_: libc::c_int, _: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        let context = unsafe { &*(context as *const libc::ucontext_t) };
+        let mut unwinder = UnwindBuilder::new().build();
+        let frames = unwinder
+            .unwind_from_ucontext(context)
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        *SIGNAL_FRAMES.lock().unwrap() = frames;
+    }
+
+    #[inline(never)]
+    fn raise_signal() -> Vec<u64> {
+        let mut unwinder = UnwindBuilder::new().build();
+        let frames = unwinder
+            .unwind()
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        unsafe { libc::raise(libc::SIGUSR1) };
+        black_box(frames)
+    }
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+    }
+    let frames = raise_signal();
+    let signal_frames = SIGNAL_FRAMES.lock().unwrap().clone();
+
+    // Both walks leave `raise_signal` through the same return address.
+    assert!(
+        signal_frames.ends_with(&frames[2..]),
+        "The interrupted stack should include the callers of raise_signal. Found {:x?}, expected to end with {:x?}",
+        signal_frames,
+        &frames[2..]
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_through_signal_frame() {
+    use hopframe::unwinder::{Frame, FrameKind};
+    use std::sync::Mutex;
+
+    static SIGNAL_FRAMES: Mutex<(Vec<Frame>, Vec<Frame>)> = Mutex::new((Vec::new(), Vec::new()));
+
+    extern "C" fn handler(_: libc::c_int, _: *mut libc::siginfo_t, context: *mut libc::c_void) {
+        let context = unsafe { &*(context as *const libc::ucontext_t) };
+        let mut unwinder = UnwindBuilder::new().build();
+        let live_frames = unwinder.unwind().collect();
+        let interrupted_frames = unwinder.unwind_from_ucontext(context).collect();
+        *SIGNAL_FRAMES.lock().unwrap() = (live_frames, interrupted_frames);
+    }
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigaction(libc::SIGUSR2, &action, std::ptr::null_mut());
+        libc::raise(libc::SIGUSR2);
+    }
+    let (live_frames, interrupted_frames) = SIGNAL_FRAMES.lock().unwrap().clone();
+    let lookup = |frames: &[Frame]| -> Vec<u64> {
+        frames
+            .iter()
+            .map(|frame| frame.address_for_lookup())
+            .collect()
+    };
+
+    // The walk from the handler continues into the interrupted code.
+    assert!(
+        lookup(&live_frames).ends_with(&lookup(&interrupted_frames)),
+        "The walk should continue past the signal frame. Found {:x?}, expected to end with {:x?}",
+        lookup(&live_frames),
+        lookup(&interrupted_frames)
+    );
+    let interrupted = &live_frames[live_frames.len() - interrupted_frames.len()];
+    assert_eq!(interrupted.kind(), FrameKind::InterruptedBySignal);
+}
+
+#[test]
+fn test_unwind_from_corrupted_regs() {
+    let local = 0u64;
+    let sp = &local as *const u64 as u64;
+    let mut unwinder = UnwindBuilder::new().build();
+
+    #[cfg(target_arch = "x86_64")]
+    let mut iter = unwinder.unwind_from_regs(0x1234, sp, 0xdead_beef);
+    #[cfg(target_arch = "aarch64")]
+    let mut iter = unwinder.unwind_from_regs(0x1234, sp, 0xdead_beef, 0);
+
+    let frames: Vec<u64> = iter
+        .by_ref()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+    assert_eq!(frames, [0x1234]);
+    let reason = iter
+        .termination_reason()
+        .expect("The walk should have ended");
+    assert!(!reason.is_complete(), "Unexpected reason: {}", reason);
+}
+
+#[test]
+fn test_capture_into() {
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames: Vec<u64> = unwinder.unwind().map(|frame| frame.address()).collect();
+    let mut buffer = [0; 128];
+    let count = unwinder.capture_into(&mut buffer);
+
+    // The first two frames are inside the unwinder and in this function, at different call sites.
+    assert_eq!(buffer[2..count], frames[2..]);
+
+    let mut small_buffer = [0; 3];
+    assert_eq!(unwinder.capture_into(&mut small_buffer), 3);
+}
+
+#[test]
+fn test_frame_details() {
+    use hopframe::unwinder::FrameKind;
+
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames: Vec<_> = unwinder.unwind().collect();
+
+    assert_eq!(frames[0].kind(), FrameKind::InstructionPointer);
+    assert_eq!(frames[0].address_for_lookup(), frames[0].address());
+    for frame in &frames[1..] {
+        assert_eq!(frame.kind(), FrameKind::ReturnAddress);
+        assert_eq!(frame.address_for_lookup(), frame.address() - 1);
+    }
+    // The stack grows down, so the callers are at higher addresses.
+    for pair in frames.windows(2) {
+        assert!(pair[0].stack_pointer() <= pair[1].stack_pointer());
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let index = frames[1]
+            .module_index()
+            .expect("This function should be in a known module");
+        let module = unwinder.module(index).unwrap();
+        assert_eq!(*module.path, std::env::current_exe().unwrap());
+        assert!(module.avma_range.contains(&frames[1].address()));
+    }
+}
+
+#[test]
+fn test_capture() {
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames: Vec<u64> = unwinder.unwind().map(|frame| frame.address()).collect();
+    let captured: Vec<u64> = hopframe::capture()
+        .iter()
+        .map(|frame| frame.address())
+        .collect();
+
+    // The capture starts in this function, the live walk inside the unwinder.
+    assert_eq!(captured[1..], frames[2..]);
+
+    // A second capture on another thread builds that thread's own unwinder.
+    let frame_count = std::thread::spawn(|| hopframe::capture().len())
+        .join()
+        .unwrap();
+    assert!(frame_count > 1, "Found {} frames", frame_count);
+}
+
+#[test]
+fn test_trace() {
+    let mut visited = 0;
+    hopframe::trace(|_frame| {
+        visited += 1;
+        visited < 2
+    });
+    assert_eq!(visited, 2);
+
+    // Capturing from inside the callback falls back to a temporary unwinder.
+    let mut nested = 0;
+    hopframe::trace(|_frame| {
+        nested = hopframe::capture().len();
+        false
+    });
+    assert!(nested > 1, "Found {} frames", nested);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_thread() {
+    use hopframe::unwinder::gettid;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc,
+        },
+        time::Duration,
+    };
+
+    #[inline(never)]
+    fn busy_worker(stop: &AtomicBool, sender: mpsc::Sender<(i32, Vec<u64>)>) {
+        let mut unwinder = UnwindBuilder::new().build();
+        let frames = unwinder
+            .unwind()
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        sender.send((gettid(), frames)).unwrap();
+        while !stop.load(Ordering::Relaxed) {
+            std::hint::spin_loop();
+        }
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    let worker = {
+        let stop = stop.clone();
+        std::thread::spawn(move || busy_worker(&stop, sender))
+    };
+    let (tid, worker_frames) = receiver.recv().unwrap();
+
+    let mut unwinder = UnwindBuilder::new().build();
+    let capture = unwinder
+        .unwind_thread(tid, Duration::from_secs(5))
+        .expect("Should capture the worker thread");
+    assert_ne!(capture.termination_reason, TerminationReason::DepthLimit);
+    let frames: Vec<u64> = capture
+        .frames
+        .into_iter()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+
+    // A truncated stack is reported as such, and a huge limit doesn't allocate up front.
+    let capture = UnwindBuilder::new()
+        .with_max_frames(2)
+        .build()
+        .unwind_thread(tid, Duration::from_secs(5))
+        .expect("Should capture the worker thread");
+    assert_eq!(capture.frames.len(), 2);
+    assert_eq!(capture.termination_reason, TerminationReason::DepthLimit);
+    let capture = UnwindBuilder::new()
+        .with_max_frames(usize::MAX)
+        .build()
+        .unwind_thread(tid, Duration::from_secs(5))
+        .expect("Should capture the worker thread");
+    assert_ne!(capture.termination_reason, TerminationReason::DepthLimit);
+    stop.store(true, Ordering::Relaxed);
+    worker.join().unwrap();
+
+    // Both walks leave `busy_worker` through the same return address.
+    assert!(
+        frames.ends_with(&worker_frames[2..]),
+        "The worker's stack should include the callers of busy_worker. Found {:x?}, expected to end with {:x?}",
+        frames,
+        &worker_frames[2..]
+    );
+
+    assert!(matches!(
+        unwinder.unwind_thread(gettid(), Duration::from_secs(1)),
+        Err(hopframe::unwinder::ThreadCaptureError::CurrentThread)
+    ));
+}
+
+/// Set for the child process of `test_unwind_thread_with_signal_in_use`.
+#[cfg(target_os = "linux")]
+const SIGNAL_IN_USE_ENV: &str = "HOPFRAME_TEST_SIGNAL_IN_USE";
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_thread_with_signal_in_use() {
+    use hopframe::unwinder::ThreadCaptureError;
+    use std::time::Duration;
+
+    extern "C" fn application_handler(_: libc::c_int) {}
+
+    // The handler is installed once per process, so the test runs alone in a child process,
+    // before any other test can install it.
+    if std::env::var_os(SIGNAL_IN_USE_ENV).is_none() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "test_unwind_thread_with_signal_in_use"])
+            .env(SIGNAL_IN_USE_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stdout)
+        );
+        return;
+    }
+
+    let signal = libc::SIGRTMIN() + 4;
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = application_handler as *const () as libc::sighandler_t;
+    assert_eq!(
+        unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) },
+        0
+    );
+
+    // The test harness runs tests on other threads than the main one.
+    let main_thread = unsafe { libc::getpid() };
+    let mut unwinder = UnwindBuilder::new().build();
+    assert!(matches!(
+        unwinder.unwind_thread(main_thread, Duration::from_secs(1)),
+        Err(ThreadCaptureError::SignalInUse)
+    ));
+
+    // The handler of the application is kept.
+    let mut current: libc::sigaction = unsafe { std::mem::zeroed() };
+    assert_eq!(
+        unsafe { libc::sigaction(signal, std::ptr::null(), &mut current) },
+        0
+    );
+    assert_eq!(current.sa_sigaction, action.sa_sigaction);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_stack_watermark() {
+    use hopframe::unwinder::{gettid, StackWatermark};
+    use std::{sync::mpsc, time::Duration};
+
+    const BUFFER_SIZE: usize = 1024;
+    const DEPTH: u32 = 64;
+
+    /// Waits at the bottom of `DEPTH` frames of `BUFFER_SIZE` bytes for the sampler.
+    #[inline(never)]
+    fn recurse(current: u32, waiting: &mpsc::Sender<()>, resume: &mpsc::Receiver<()>) -> u8 {
+        let buffer = black_box([current as u8; BUFFER_SIZE]);
+        if current < DEPTH {
+            black_box(recurse(current + 1, waiting, resume));
+        } else {
+            waiting.send(()).unwrap();
+            resume.recv().unwrap();
+        }
+        black_box(&buffer)[0]
+    }
+
+    let (watermark_sender, watermark_receiver) = mpsc::channel();
+    let (returned_watermark, returned_watermark_receiver) = mpsc::channel();
+    let (waiting_sender, waiting) = mpsc::channel();
+    let (resume, resume_receiver) = mpsc::channel();
+    let worker = std::thread::spawn(move || {
+        let stack = StackBounds::current_thread().unwrap();
+        // Threads spawned with `std::thread` have their whole stack mapped.
+        let watermark = unsafe { StackWatermark::paint(stack) }.expect("Should paint the stack");
+        // The stack is only read on its own thread, which isn't writing to it meanwhile.
+        let painted_used_bytes = unsafe { watermark.max_used_bytes() };
+        watermark_sender
+            .send((gettid(), watermark, painted_used_bytes))
+            .unwrap();
+        // Idle, then deep in the recursion, then idle again.
+        for depth in [DEPTH, 0, DEPTH] {
+            black_box(recurse(depth, &waiting_sender, &resume_receiver));
+        }
+        let watermark: StackWatermark = returned_watermark_receiver.recv().unwrap();
+        let deepest_touched_address = unsafe { watermark.deepest_touched_address() };
+        let max_used_bytes = unsafe { watermark.max_used_bytes() };
+        (watermark, deepest_touched_address, max_used_bytes)
+    });
+
+    let (tid, mut watermark, painted_used_bytes) = watermark_receiver.recv().unwrap();
+    let mut unwinder = UnwindBuilder::new().build();
+    let mut deepest_sp = Vec::new();
+    for _ in 0..3 {
+        waiting.recv().unwrap();
+        watermark
+            .sample_thread(&mut unwinder, tid, Duration::from_secs(5))
+            .expect("Should sample the worker thread");
+        deepest_sp.push(watermark.deepest_walk().unwrap()[0].stack_pointer());
+        resume.send(()).unwrap();
+    }
+    returned_watermark.send(watermark).unwrap();
+    let (watermark, deepest_touched_address, max_used_bytes) = worker.join().unwrap();
+
+    // The deep sample replaced the idle one, and was kept over the next idle one.
+    assert!(deepest_sp[1] + (DEPTH as u64 * BUFFER_SIZE as u64) <= deepest_sp[0]);
+    assert_eq!(deepest_sp[2], deepest_sp[1]);
+    assert!(deepest_touched_address < deepest_sp[1]);
+    assert!(max_used_bytes >= painted_used_bytes + DEPTH as u64 * BUFFER_SIZE as u64);
+    assert!(watermark.stack().contains(deepest_touched_address, 8));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_dump_all_threads() {
+    use hopframe::unwinder::gettid;
+    use std::sync::mpsc;
+
+    let (tid_sender, tid_receiver) = mpsc::channel();
+    let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+    let worker = std::thread::Builder::new()
+        .name("dump-worker".into())
+        .spawn(move || {
+            tid_sender.send(gettid()).unwrap();
+            stop_receiver.recv().ok();
+        })
+        .unwrap();
+    let worker_tid = tid_receiver.recv().unwrap();
+
+    let lookup = |frames: &[hopframe::unwinder::Frame]| -> Vec<u64> {
+        frames
+            .iter()
+            .map(|frame| frame.address_for_lookup())
+            .collect()
+    };
+    let mut unwinder = UnwindBuilder::new().with_skip_internal_frame(true).build();
+    let direct: Vec<_> = unwinder.unwind().collect();
+    let stacks = hopframe::dump_all_threads().expect("Should list the threads");
+    stop_sender.send(()).unwrap();
+    worker.join().unwrap();
+
+    for tid in [gettid(), worker_tid] {
+        let stack = stacks
+            .iter()
+            .find(|stack| stack.tid == tid)
+            .expect("Every thread should be dumped");
+        let frames = stack.frames.as_ref().expect("Should capture the thread");
+        assert!(!frames.is_empty());
+    }
+    // The stack of this thread starts here, like the direct walk.
+    let current_stack = stacks.iter().find(|stack| stack.tid == gettid()).unwrap();
+    let current_frames = current_stack.frames.as_ref().unwrap();
+    assert_eq!(lookup(&current_frames[1..]), lookup(&direct[1..]));
+    let worker_stack = stacks.iter().find(|stack| stack.tid == worker_tid).unwrap();
+    assert_eq!(worker_stack.name, "dump-worker");
+    assert!(worker_stack.to_string().starts_with("\"dump-worker\""));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_remote_unwinder() {
+    use hopframe::unwinder::RemoteUnwinder;
+    use std::process::Command;
+
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    // Let the child reach its sleep.
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    let stacks = RemoteUnwinder::new(child.id() as i32)
+        .and_then(|mut unwinder| unwinder.unwind_all_threads());
+    // The child is left running, with no stop pending.
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", child.id())).unwrap();
+    let state = stat.rsplit(") ").next().unwrap().chars().next();
+    assert_ne!(
+        state,
+        Some('T'),
+        "The child should not be stopped: {}",
+        stat
+    );
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let stacks = stacks.expect("Should unwind the child process");
+    assert_eq!(stacks.len(), 1);
+    let stack = &stacks[0];
+    assert_eq!(stack.tid, child.id() as i32);
+    assert_eq!(stack.name, "sleep");
+    assert!(
+        stack.frames.len() > 2,
+        "Should unwind from the sleep syscall to main. Found:\n{}",
+        stack
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_from_vdso() {
+    use hopframe::unwinder::StackSnapshot;
+
+    #[cfg(target_arch = "x86_64")]
+    const CLOCK_GETTIME: &[u8] = b"__vdso_clock_gettime\0";
+    #[cfg(target_arch = "aarch64")]
+    const CLOCK_GETTIME: &[u8] = b"__kernel_clock_gettime\0";
+
+    // qemu-user before 8.1 doesn't map a vDSO.
+    if unsafe { libc::getauxval(libc::AT_SYSINFO_EHDR) } == 0 {
+        eprintln!("Skipped: no vDSO in this process");
+        return;
+    }
+
+    let vdso = unsafe {
+        libc::dlopen(
+            b"linux-vdso.so.1\0".as_ptr() as *const libc::c_char,
+            libc::RTLD_LAZY | libc::RTLD_NOLOAD,
+        )
+    };
+    assert!(!vdso.is_null(), "The vDSO should be loaded");
+    let clock_gettime =
+        unsafe { libc::dlsym(vdso, CLOCK_GETTIME.as_ptr() as *const libc::c_char) } as u64;
+    assert_ne!(clock_gettime, 0);
+
+    // A stack stopped on the first instruction of `clock_gettime`, called from here.
+    let return_address = test_unwind_from_vdso as *const () as u64 + 1;
+    let mut stack = vec![0; 64];
+    stack[..8].copy_from_slice(&return_address.to_ne_bytes());
+    let snapshot = StackSnapshot {
+        pc: clock_gettime,
+        sp: 0x10000,
+        fp: 0,
+        lr: return_address,
+        stack_base: 0x10000,
+        stack,
+        modules: Vec::new(),
+    };
+
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames: Vec<_> = unwinder.unwind_snapshot(&snapshot).collect();
+    assert!(frames.len() >= 2, "Found {:x?}", frames);
+    assert_eq!(frames[1].address(), return_address);
+    let module = frames[0]
+        .module_index()
+        .and_then(|index| unwinder.module(index))
+        .expect("The vDSO should be a known module");
+    assert_eq!(&*module.path, std::path::Path::new("[vdso]"));
+}
+
+/// Code mapped as executable, called as `extern "C" fn(callback: extern "C" fn())`. It is
+/// unmapped when dropped.
+#[cfg(target_os = "linux")]
+struct MappedCode {
+    code: *mut libc::c_void,
+    range: std::ops::Range<u64>,
+}
+
+#[cfg(target_os = "linux")]
+impl MappedCode {
+    fn new(code: &[u8]) -> Self {
+        let mapped = unsafe {
+            let mapped = libc::mmap(
+                std::ptr::null_mut(),
+                4096,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            assert_ne!(mapped, libc::MAP_FAILED);
+            std::ptr::copy_nonoverlapping(code.as_ptr(), mapped as *mut u8, code.len());
+            libc::mprotect(mapped, 4096, libc::PROT_READ | libc::PROT_EXEC);
+            // The instruction cache doesn't see the code written through the data cache.
+            #[cfg(target_arch = "aarch64")]
+            std::arch::asm!(
+                "dc cvau, {0}",
+                "dsb ish",
+                "ic ivau, {0}",
+                "dsb ish",
+                "isb",
+                in(reg) mapped,
+            );
+            mapped
+        };
+        let start = mapped as u64;
+        Self {
+            code: mapped,
+            range: start..start + code.len() as u64,
+        }
+    }
+
+    /// Calls the code with a callback that walks the stack with `unwinder`. Returns the
+    /// frames of that walk, and the addresses for lookup of a walk from this function instead,
+    /// whose frames from the third on are the ones past the code.
+    #[inline(never)]
+    fn call(
+        &self,
+        unwinder: &mut hopframe::unwinder::StackUnwinder,
+    ) -> (Vec<hopframe::unwinder::Frame>, Vec<u64>) {
+        use hopframe::unwinder::{Frame, StackUnwinder};
+        use std::cell::{Cell, RefCell};
+
+        thread_local! {
+            static UNWINDER: Cell<*mut StackUnwinder> = const { Cell::new(std::ptr::null_mut()) };
+            static FRAMES: RefCell<Vec<Frame>> = const { RefCell::new(Vec::new()) };
+        }
+
+        extern "C" fn callback() {
+            let unwinder = unsafe { &mut *UNWINDER.with(Cell::get) };
+            let frames = unwinder.unwind().collect();
+            FRAMES.with(|cell| *cell.borrow_mut() = frames);
+        }
+
+        let direct = unwinder
+            .unwind()
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        let call: extern "C" fn(extern "C" fn()) = unsafe { std::mem::transmute(self.code) };
+        UNWINDER.with(|cell| cell.set(unwinder));
+        call(callback);
+        UNWINDER.with(|cell| cell.set(std::ptr::null_mut()));
+        (FRAMES.with(|cell| cell.take()), direct)
+    }
+
+    /// Returns the index of the first frame in the code.
+    fn frame_in(&self, frames: &[hopframe::unwinder::Frame]) -> usize {
+        frames
+            .iter()
+            .position(|frame| self.range.contains(&frame.address()))
+            .expect("Should find the frame of the code")
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for MappedCode {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.code, 4096) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_through_registered_code() {
+    use hopframe::unwinder::CodeUnwindInfo;
+
+    // Calls the function in the first argument register, without a frame pointer:
+    // `sub rsp, 8; call rdi; add rsp, 8; ret`.
+    #[cfg(target_arch = "x86_64")]
+    const CODE: &[u8] = &[
+        0x48, 0x83, 0xec, 0x08, 0xff, 0xd7, 0x48, 0x83, 0xc4, 0x08, 0xc3,
+    ];
+    // `sub sp, sp, #16; str x30, [sp, #8]; blr x0; ldr x30, [sp, #8]; add sp, sp, #16; ret`.
+    #[cfg(target_arch = "aarch64")]
+    const CODE: &[u8] = &[
+        0xff, 0x43, 0x00, 0xd1, 0xfe, 0x07, 0x00, 0xf9, 0x00, 0x00, 0x3f, 0xd6, 0xfe, 0x07, 0x40,
+        0xf9, 0xff, 0x43, 0x00, 0x91, 0xc0, 0x03, 0x5f, 0xd6,
+    ];
+
+    /// A CIE and an FDE for `CODE` at `start`, with absolute addresses.
+    fn eh_frame(start: u64) -> Vec<u8> {
+        #[cfg(target_arch = "x86_64")]
+        let (code_align, return_register, initial, instructions): (u8, u8, &[u8], &[u8]) = (
+            1,
+            16,
+            // CFA = rsp + 8, return address at CFA - 8.
+            &[0x0c, 0x07, 0x08, 0x90, 0x01],
+            // After `sub`: CFA = rsp + 16. After `add`: CFA = rsp + 8.
+            &[0x44, 0x0e, 0x10, 0x46, 0x0e, 0x08],
+        );
+        #[cfg(target_arch = "aarch64")]
+        let (code_align, return_register, initial, instructions): (u8, u8, &[u8], &[u8]) = (
+            4,
+            30,
+            // CFA = sp.
+            &[0x0c, 0x1f, 0x00],
+            // After `sub`: CFA = sp + 16. After `str`: x30 at CFA - 8. After `add`: CFA = sp.
+            &[0x41, 0x0e, 0x10, 0x41, 0x9e, 0x01, 0x43, 0x0e, 0x00],
+        );
+
+        fn entry(data: &mut Vec<u8>, body: &[u8]) {
+            let len = (body.len() + 4 + 7) / 8 * 8 - 4;
+            data.extend_from_slice(&(len as u32).to_le_bytes());
+            data.extend_from_slice(body);
+            data.resize(data.len() + len - body.len(), 0);
+        }
+
+        let mut data = Vec::new();
+        let mut cie = vec![
+            0,
+            0,
+            0,
+            0,
+            1,
+            b'z',
+            b'R',
+            0,
+            code_align,
+            0x78,
+            return_register,
+            1,
+            0,
+        ];
+        cie.extend_from_slice(initial);
+        entry(&mut data, &cie);
+
+        let mut fde = Vec::new();
+        // Offset back to the CIE, from this field.
+        fde.extend_from_slice(&(data.len() as u32 + 4).to_le_bytes());
+        fde.extend_from_slice(&start.to_le_bytes());
+        fde.extend_from_slice(&(CODE.len() as u64).to_le_bytes());
+        fde.push(0);
+        fde.extend_from_slice(instructions);
+        entry(&mut data, &fde);
+        data
+    }
+
+    let code = MappedCode::new(CODE);
+    let mut unwinder = UnwindBuilder::new().build();
+    // The code is unmapped after the unwinder is dropped.
+    unsafe {
+        unwinder.register_code_range(
+            code.range.start,
+            code.range.end,
+            CodeUnwindInfo::EhFrame {
+                data: eh_frame(code.range.start),
+                address: 0,
+            },
+            Some("jit_stub"),
+        )
+    };
+    let (frames, direct) = code.call(&mut unwinder);
+
+    let jit_frame = code.frame_in(&frames);
+    let lookup: Vec<u64> = frames[jit_frame + 2..]
+        .iter()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+    // Past the registered code, the walk continues into the callers of the code.
+    assert_eq!(lookup, direct[2..]);
+
+    let module = frames[jit_frame]
+        .module_index()
+        .and_then(|index| unwinder.module(index))
+        .expect("The registered code should be a known module");
+    assert_eq!(&*module.path, std::path::Path::new("jit_stub"));
+    assert!(unwinder.unregister_code_range(code.range.start));
+    assert!(!unwinder.unregister_code_range(code.range.start));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_stack_scanning() {
+    use hopframe::unwinder::FrameKind;
+
+    // Calls the function in the first argument register, with neither unwind information nor
+    // a valid frame pointer: `push rbp; mov ebp, 8; call rdi; pop rbp; ret`.
+    #[cfg(target_arch = "x86_64")]
+    const CODE: &[u8] = &[0x55, 0xbd, 0x08, 0x00, 0x00, 0x00, 0xff, 0xd7, 0x5d, 0xc3];
+    // `stp x29, x30, [sp, #-16]!; mov x29, #8; blr x0; ldp x29, x30, [sp], #16; ret`.
+    #[cfg(target_arch = "aarch64")]
+    const CODE: &[u8] = &[
+        0xfd, 0x7b, 0xbf, 0xa9, 0x1d, 0x01, 0x80, 0xd2, 0x00, 0x00, 0x3f, 0xd6, 0xfd, 0x7b, 0xc1,
+        0xa8, 0xc0, 0x03, 0x5f, 0xd6,
+    ];
+
+    let code = MappedCode::new(CODE);
+
+    // The walk ends in the code without scanning.
+    let (frames, _) = code.call(&mut UnwindBuilder::new().build());
+    assert_eq!(frames.len(), code.frame_in(&frames) + 1);
+
+    let mut unwinder = UnwindBuilder::new().with_stack_scanning(true).build();
+    let (frames, direct) = code.call(&mut unwinder);
+    let stub_frame = code.frame_in(&frames);
+    let scanned = &frames[stub_frame + 1];
+    assert_eq!(scanned.kind(), FrameKind::StackScanned);
+    assert!(scanned.module_index().is_some());
+    // Past the scanned frame, the walk continues into the callers of the code, with the
+    // frame pointer the code saved before clobbering it.
+    let lookup: Vec<u64> = frames[stub_frame + 2..]
+        .iter()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+    assert_eq!(lookup, direct[2..]);
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+#[test]
+fn test_stack_scanning_restores_frame_pointer() {
+    use hopframe::unwinder::{CodeUnwindInfo, FrameKind};
+
+    // Code with frame records but no CFI. `outer` calls `inner`, which clobbers the frame
+    // pointer before calling the function in `x0`, so that the walk has to scan for the
+    // return address into `outer`, and can then continue with the frame pointer of `outer`.
+    const CODE: [u32; 13] = [
+        // outer: stp x29, x30, [sp, #-16]!; mov x29, sp; bl inner;
+        // ldp x29, x30, [sp], #16; ret
+        0xa9bf_7bfd,
+        0x9100_03fd,
+        0x9400_0006,
+        0xa8c1_7bfd,
+        0xd65f_03c0,
+        0,
+        0,
+        0,
+        // inner: stp x29, x30, [sp, #-16]!; mov x29, #8; blr x0;
+        // ldp x29, x30, [sp], #16; ret
+        0xa9bf_7bfd,
+        0xd280_011d,
+        0xd63f_0000,
+        0xa8c1_7bfd,
+        0xd65f_03c0,
+    ];
+
+    let bytes: Vec<u8> = CODE.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let code = MappedCode::new(&bytes);
+    let start = code.range.start;
+    let mut unwinder = UnwindBuilder::new().with_stack_scanning(true).build();
+    // The code is unmapped after the unwinder is dropped.
+    unsafe {
+        unwinder.register_code_range(
+            start,
+            code.range.end,
+            CodeUnwindInfo::FramePointer,
+            Some("stub"),
+        )
+    };
+    let (frames, direct) = code.call(&mut unwinder);
+
+    let inner = frames
+        .iter()
+        .position(|frame| frame.address() == start + 44)
+        .expect("Should find the frame of inner");
+    // The return address into `outer` is found by scanning.
+    assert_eq!(frames[inner + 1].address(), start + 12);
+    assert_eq!(frames[inner + 1].kind(), FrameKind::StackScanned);
+    // `outer` is then unwound with its frame pointer, into the callers of the code.
+    assert_eq!(frames[inner + 2].kind(), FrameKind::ReturnAddress);
+    let lookup: Vec<u64> = frames[inner + 3..]
+        .iter()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+    assert_eq!(lookup, direct[2..]);
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[test]
+fn test_unwind_from_unloaded_library() {
+    // A library of glibc that is not loaded otherwise, so that `dlclose` unmaps it.
+    let library = unsafe {
+        libc::dlopen(
+            b"libthread_db.so.1\0".as_ptr().cast(),
+            libc::RTLD_LAZY | libc::RTLD_LOCAL,
+        )
+    };
+    if library.is_null() {
+        eprintln!("Skipped: can't load libthread_db.so.1");
+        return;
+    }
+    let function = unsafe { libc::dlsym(library, b"td_init\0".as_ptr().cast()) } as u64;
+    assert_ne!(function, 0);
+
+    let mut unwinder = UnwindBuilder::new().build();
+    // Registers the library with the unwinder.
+    unwinder.unwind().for_each(drop);
+    assert_eq!(unsafe { libc::dlclose(library) }, 0);
+
+    // The walk doesn't re-read the loaded images, since `function` is in a known module,
+    // and must not crash reading its code.
+    let local = 0u64;
+    let sp = &local as *const u64 as u64;
+    #[cfg(target_arch = "x86_64")]
+    let iter = unwinder.unwind_from_regs(function, sp, 0);
+    #[cfg(target_arch = "aarch64")]
+    let iter = unwinder.unwind_from_regs(function, sp, 0, 0);
+    assert!(iter.count() >= 1);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_from_prologue_and_epilogue() {
+    use hopframe::unwinder::CodeUnwindInfo;
+
+    /// Functions start at 16-byte boundaries.
+    #[repr(align(16))]
+    struct Code([u8; 48]);
+
+    // `push rbp; mov rbp, rsp; pop rbp; ret`, between `int3` padding, and `mov al, 0x55; mov
+    // rbp, rsp`, whose immediate is not a `push rbp`.
+    #[cfg(target_arch = "x86_64")]
+    let (code, prologue, epilogue) = {
+        let mut code = Code([0xcc_u8; 48]);
+        code.0[16..22].copy_from_slice(&[0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3]);
+        code.0[32..37].copy_from_slice(&[0xb0, 0x55, 0x48, 0x89, 0xe5]);
+        (code, [16, 17], 21)
+    };
+    // `stp x29, x30, [sp, #-16]!; mov x29, sp; ldp x29, x30, [sp], #16; ret`.
+    #[cfg(target_arch = "aarch64")]
+    let (code, prologue, epilogue) = {
+        let mut code = Code([0_u8; 48]);
+        code.0[16..32].copy_from_slice(&[
+            0xfd, 0x7b, 0xbf, 0xa9, 0xfd, 0x03, 0x00, 0x91, 0xfd, 0x7b, 0xc1, 0xa8, 0xc0, 0x03,
+            0x5f, 0xd6,
+        ]);
+        (code, [16, 20], 28)
+    };
+    let start = code.0.as_ptr() as u64;
+    let return_address = test_unwind_from_corrupted_regs as *const () as u64 + 4;
+    let saved_fp = 0x1000;
+    let bogus_fp = 8;
+
+    let mut unwinder = UnwindBuilder::new().build();
+    // The code outlives the unwinder.
+    unsafe {
+        unwinder.register_code_range(
+            start,
+            start + code.0.len() as u64,
+            CodeUnwindInfo::FramePointer,
+            Some("prologue_stub"),
+        )
+    };
+    let mut caller = |offset: u64, stack: &[u64; 2]| {
+        let sp = stack.as_ptr() as u64;
+        #[cfg(target_arch = "x86_64")]
+        let mut iter = unwinder.unwind_from_regs(start + offset, sp, bogus_fp);
+        #[cfg(target_arch = "aarch64")]
+        let mut iter = unwinder.unwind_from_regs(start + offset, sp, bogus_fp, return_address);
+        let frame = iter.nth(1)?;
+        Some((
+            frame.address(),
+            frame.stack_pointer() - sp,
+            frame.frame_pointer(),
+        ))
+    };
+
+    // Before the frame record is pushed, and when it has been popped again.
+    #[cfg(target_arch = "x86_64")]
+    let (stack, expected) = ([return_address, 0], (return_address, 8, bogus_fp));
+    #[cfg(target_arch = "aarch64")]
+    let (stack, expected) = ([0, 0], (return_address, 0, bogus_fp));
+    assert_eq!(caller(prologue[0], &stack), Some(expected));
+    assert_eq!(caller(epilogue, &stack), Some(expected));
+
+    // Once the frame record is pushed, but before the frame pointer points to it.
+    let stack = [saved_fp, return_address];
+    let expected = (return_address, 16, saved_fp);
+    assert_eq!(caller(prologue[1], &stack), Some(expected));
+    #[cfg(target_arch = "x86_64")]
+    assert_ne!(caller(34, &stack), Some(expected));
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+#[test]
+fn test_unwind_fiber() {
+    use std::cell::{Cell, RefCell};
+
+    const STACK_SIZE: usize = 1 << 20;
+
+    thread_local! {
+        static MAIN: Cell<*mut libc::ucontext_t> = const { Cell::new(std::ptr::null_mut()) };
+        static FIBER: Cell<*mut libc::ucontext_t> = const { Cell::new(std::ptr::null_mut()) };
+        static FIBER_STACK: Cell<Option<StackBounds>> = const { Cell::new(None) };
+        static FRAMES: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// Records the frames of the fiber as seen from inside, then switches back to the main
+    /// context.
+    #[inline(never)]
+    fn park() -> u64 {
+        let stack = FIBER_STACK.with(Cell::get).unwrap();
+        let mut context: libc::ucontext_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::getcontext(&mut context) };
+        let mut unwinder = UnwindBuilder::new().build();
+        // The fiber stack is mapped until the end of the test.
+        let frames = unsafe { unwinder.unwind_fiber_from_ucontext(&context, stack) }
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        FRAMES.with(|cell| *cell.borrow_mut() = frames);
+        unsafe { libc::swapcontext(FIBER.with(Cell::get), MAIN.with(Cell::get)) };
+        0
+    }
+
+    #[inline(never)]
+    fn fiber_level_2() -> u64 {
+        black_box(park()) + 1
+    }
+
+    extern "C" fn fiber_main() {
+        black_box(fiber_level_2());
+    }
+
+    let stack = unsafe {
+        let stack = libc::mmap(
+            std::ptr::null_mut(),
+            STACK_SIZE,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        assert_ne!(stack, libc::MAP_FAILED);
+        stack
+    };
+    let bounds = StackBounds::new(stack as u64, stack as u64 + STACK_SIZE as u64);
+    let mut main: libc::ucontext_t = unsafe { std::mem::zeroed() };
+    let mut fiber: libc::ucontext_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::getcontext(&mut fiber);
+        fiber.uc_stack.ss_sp = stack;
+        fiber.uc_stack.ss_size = STACK_SIZE;
+        fiber.uc_link = &mut main;
+        libc::makecontext(&mut fiber, fiber_main, 0);
+    }
+    MAIN.with(|cell| cell.set(&mut main));
+    FIBER.with(|cell| cell.set(&mut fiber));
+    FIBER_STACK.with(|cell| cell.set(Some(bounds)));
+    unsafe { libc::swapcontext(&mut main, &fiber) };
+
+    // The fiber is now parked in `park`.
+    let inside = FRAMES.with(|cell| cell.take());
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames: Vec<hopframe::unwinder::Frame> =
+        unsafe { unwinder.unwind_fiber_from_ucontext(&fiber, bounds) }.collect();
+    assert!(frames
+        .iter()
+        .all(|frame| bounds.contains(frame.stack_pointer(), 0)));
+    let frames: Vec<u64> = frames
+        .iter()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+    // The first frames are in `park`, at different call sites.
+    assert!(frames.len() > 3, "Too few frames: {:x?}", frames);
+    assert_eq!(frames[1..], inside[1..]);
+
+    // The stack of the thread doesn't lead anywhere from the context of the fiber.
+    assert_eq!(unwinder.unwind_from_ucontext(&fiber).count(), 1);
+
+    // Let the fiber return.
+    unsafe {
+        libc::swapcontext(&mut main, &fiber);
+        libc::munmap(stack, STACK_SIZE);
+    }
+}
+
+#[test]
+fn test_unwind_snapshot() {
+    use hopframe::unwinder::StackSnapshot;
+
+    #[inline(never)]
+    fn take_snapshot(max_stack_bytes: usize) -> (Vec<u64>, StackSnapshot) {
+        let mut unwinder = UnwindBuilder::new().build();
+        let frames = unwinder
+            .unwind()
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        let snapshot = unwinder.snapshot(max_stack_bytes);
+        (frames, snapshot)
+    }
+
+    let (frames, snapshot) = take_snapshot(1 << 20);
+    let snapshot_frames: Vec<u64> = std::thread::spawn(move || {
+        let mut unwinder = UnwindBuilder::new().build();
+        let frames = unwinder
+            .unwind_snapshot(&snapshot)
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        frames
+    })
+    .join()
+    .unwrap();
+    // Both walks leave `take_snapshot` through the same return address.
+    assert_eq!(snapshot_frames[2..], frames[2..]);
+
+    // A partial copy of the stack ends the walk early.
+    let (_, snapshot) = take_snapshot(256);
+    assert_eq!(snapshot.stack.len(), 256);
+    let mut unwinder = UnwindBuilder::new().build();
+    let mut iter = unwinder.unwind_snapshot(&snapshot);
+    assert!(iter.by_ref().count() < snapshot_frames.len());
+    assert!(matches!(
+        iter.termination_reason(),
+        Some(TerminationReason::MemoryReadFailed { .. })
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_snapshot_keeps_modules() {
+    use hopframe::unwinder::{SnapshotModule, StackSnapshot, StackUnwinder};
+
+    #[inline(never)]
+    fn capture(unwinder: &mut StackUnwinder) -> Vec<u64> {
+        let mut buffer = [0; 128];
+        let count = unwinder.capture_into(&mut buffer);
+        buffer[..count].to_vec()
+    }
+
+    let mut unwinder = UnwindBuilder::new().build();
+    let before = capture(&mut unwinder);
+
+    // A snapshot of a process with a single image, none of which are loaded here.
+    let snapshot = StackSnapshot {
+        pc: 0x1000,
+        sp: 0x10000,
+        fp: 0,
+        lr: 0,
+        stack_base: 0x10000,
+        stack: vec![0; 64],
+        modules: vec![SnapshotModule {
+            path: std::path::Path::new("/nonexistent/libfoo.so").into(),
+            bias: 0,
+            avma_range: 0x1000..0x2000,
+        }],
+    };
+    let frames: Vec<_> = unwinder.unwind_snapshot(&snapshot).collect();
+    let module = frames[0]
+        .module_index()
+        .and_then(|index| unwinder.module(index))
+        .expect("The frame should be in the module of the snapshot");
+    assert_eq!(
+        &*module.path,
+        std::path::Path::new("/nonexistent/libfoo.so")
+    );
+
+    // `capture_into` can't re-read the images, so it must still have them. The third frame
+    // is the call site in this function.
+    let after = capture(&mut unwinder);
+    assert_eq!(after.len(), before.len());
+    assert_eq!(after[..2], before[..2]);
+    assert_eq!(after[3..], before[3..]);
+}
+
+// A leaf function that spins until `*flag != 0`. It makes no calls, so its return address
+// stays in `lr` on aarch64 (and on top of the stack on x86_64), as described by its CFI.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+std::arch::global_asm!(
+    ".text",
+    ".globl hopframe_test_leaf_spin",
+    ".type hopframe_test_leaf_spin, @function",
+    "hopframe_test_leaf_spin:",
+    ".cfi_startproc",
+    "2:",
+    "pause",
+    "mov eax, dword ptr [rdi]",
+    "test eax, eax",
+    "jz 2b",
+    "ret",
+    ".cfi_endproc",
+    ".size hopframe_test_leaf_spin, . - hopframe_test_leaf_spin",
+);
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+std::arch::global_asm!(
+    ".text",
+    ".globl hopframe_test_leaf_spin",
+    ".type hopframe_test_leaf_spin, @function",
+    "hopframe_test_leaf_spin:",
+    ".cfi_startproc",
+    "2:",
+    "yield",
+    "ldr w1, [x0]",
+    "cbz w1, 2b",
+    "ret",
+    ".cfi_endproc",
+    ".size hopframe_test_leaf_spin, . - hopframe_test_leaf_spin",
+);
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_unwind_thread_in_leaf_function() {
+    use hopframe::unwinder::gettid;
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            mpsc, Arc,
+        },
+        time::Duration,
+    };
+
+    extern "C" {
+        fn hopframe_test_leaf_spin(flag: *const AtomicU32);
+    }
+
+    #[inline(never)]
+    fn call_leaf(flag: &AtomicU32, sender: mpsc::Sender<(i32, Vec<u64>)>) {
+        let mut unwinder = UnwindBuilder::new().build();
+        let frames = unwinder
+            .unwind()
+            .map(|frame| frame.address_for_lookup())
+            .collect();
+        sender.send((gettid(), frames)).unwrap();
+        unsafe { hopframe_test_leaf_spin(flag) };
+    }
+
+    let flag = Arc::new(AtomicU32::new(0));
+    let (sender, receiver) = mpsc::channel();
+    let worker = {
+        let flag = flag.clone();
+        std::thread::spawn(move || call_leaf(&flag, sender))
+    };
+    let (tid, worker_frames) = receiver.recv().unwrap();
+    // Give the worker time to enter the leaf function.
+    std::thread::sleep(Duration::from_millis(100));
+
+    let mut unwinder = UnwindBuilder::new().build();
+    let frames: Vec<u64> = unwinder
+        .unwind_thread(tid, Duration::from_secs(5))
+        .expect("Should capture the worker thread")
+        .frames
+        .into_iter()
+        .map(|frame| frame.address_for_lookup())
+        .collect();
+    flag.store(1, Ordering::Relaxed);
+    worker.join().unwrap();
+
+    // The leaf function's caller must not be skipped: both walks have a frame for the leaf
+    // (or `unwind`), one for `call_leaf`, and the same callers.
+    assert_eq!(frames.len(), worker_frames.len(), "Found {:x?}", frames);
+    assert_eq!(frames[2..], worker_frames[2..]);
+}
+
+#[cfg(target_arch = "aarch64")]
+#[test]
+fn test_ptr_auth_mask() {
+    use hopframe::unwinder::{PtrAuthMask, StackSnapshot};
+
+    // Two frame records outside of any module: the first one holds a return address signed
+    // with pointer authentication bits, the second one ends the stack.
+    let base = 0x10_0000;
+    let signed_return_address = 0x002a_0000_0000_3000;
+    let words: [u64; 6] = [0, 0, base + 32, signed_return_address, 0, 0];
+    let snapshot = StackSnapshot {
+        pc: 0x2000,
+        sp: base,
+        fp: base + 16,
+        lr: 0,
+        stack_base: base,
+        stack: words.iter().flat_map(|word| word.to_ne_bytes()).collect(),
+        modules: Vec::new(),
+    };
+
+    let mut unwinder = UnwindBuilder::new()
+        .with_ptr_auth_mask(PtrAuthMask(u64::MAX >> 16))
+        .build();
+    let frames: Vec<u64> = unwinder
+        .unwind_snapshot(&snapshot)
+        .map(|frame| frame.address())
+        .collect();
+    assert_eq!(frames, [0x2000, 0x3000]);
+
+    // By default, the bits stripped are the ones `xpaclri` strips on this CPU, which are
+    // none without pointer authentication. Bits 53 and 54 are above any user address, even
+    // with 52-bit virtual addresses.
+    #[cfg(target_os = "linux")]
+    {
+        let signed_return_address: u64 = 0x0060_0000_0000_3000;
+        let mut snapshot = snapshot;
+        snapshot.stack[24..32].copy_from_slice(&signed_return_address.to_ne_bytes());
+        let has_ptr_auth = unsafe { libc::getauxval(libc::AT_HWCAP) } & libc::HWCAP_PACA != 0;
+        let frames: Vec<u64> = UnwindBuilder::new()
+            .build()
+            .unwind_snapshot(&snapshot)
+            .map(|frame| frame.address())
+            .collect();
+        let expected = if has_ptr_auth {
+            0x3000
+        } else {
+            signed_return_address
+        };
+        assert_eq!(frames, [0x2000, expected]);
+    }
+}
+
+#[test]
+fn test_unwind_snapshot_of_other_arch() {
+    use hopframe::unwinder::{Arch, PtrAuthMask, SnapshotUnwinder, StackSnapshot};
+
+    fn snapshot(pc: u64, fp: u64, lr: u64, base: u64, words: &[u64]) -> StackSnapshot {
+        StackSnapshot {
+            pc,
+            sp: base,
+            fp,
+            lr,
+            stack_base: base,
+            stack: words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+            modules: Vec::new(),
+        }
+    }
+
+    // Two frame records outside of any module, the second one ending the stack.
+    let base = 0x10_0000;
+    let x86_64 = snapshot(
+        0x2000,
+        base + 16,
+        0,
+        base,
+        &[0, 0, base + 32, 0x3000, 0, 0x4000],
+    );
+    let mut unwinder = SnapshotUnwinder::new(Arch::X86_64);
+    let mut iter = unwinder.unwind_snapshot(&x86_64);
+    let frames: Vec<u64> = iter.by_ref().map(|frame| frame.address()).collect();
+    assert_eq!(frames, [0x2000, 0x3000, 0x4000]);
+    assert_eq!(
+        iter.termination_reason(),
+        Some(&TerminationReason::EndOfStack)
+    );
+
+    // Two frame records: the first one holds a return address signed with pointer
+    // authentication bits, the second one ends the stack.
+    let signed_return_address = 0x002a_0000_0000_3000;
+    let aarch64 = snapshot(
+        0x2000,
+        base + 16,
+        0,
+        base,
+        &[0, 0, base + 32, signed_return_address, 0, 0],
+    );
+    let mut unwinder =
+        SnapshotUnwinder::new(Arch::Aarch64).with_ptr_auth_mask(PtrAuthMask(u64::MAX >> 16));
+    assert_eq!(unwinder.arch(), Arch::Aarch64);
+    let frames: Vec<u64> = unwinder
+        .unwind_snapshot(&aarch64)
+        .map(|frame| frame.address())
+        .collect();
+    assert_eq!(frames, [0x2000, 0x3000]);
+
+    // A snapshot of this process is unwound with the files of its modules.
+    #[cfg(target_os = "linux")]
+    {
+        let mut unwinder = UnwindBuilder::new().build();
+        let snapshot = unwinder.snapshot(1 << 20);
+        let expected: Vec<u64> = unwinder
+            .unwind_snapshot(&snapshot)
+            .map(|frame| frame.address())
+            .collect();
+        let frames: Vec<u64> = SnapshotUnwinder::new(Arch::HOST)
+            .unwind_snapshot(&snapshot)
+            .map(|frame| frame.address())
+            .collect();
+        assert_eq!(frames, expected);
+    }
+}